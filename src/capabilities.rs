@@ -0,0 +1,134 @@
+use std::{fmt, path::Path, ptr};
+
+use libc::{c_long, ENOSYS};
+
+use crate::cgroup;
+use crate::errno::errno;
+use crate::linux_version::LinuxVersion;
+use crate::memory::mcl_onfault;
+use crate::memory::pressure::MEMORY_PRESSURE;
+use crate::memory::pressure::{PressureKind, PressureSnapshot, PressureTrigger, TriggerSpec};
+
+/// `process_mrelease` is too recent (Linux 5.15) to be in our version of `libc`.
+/// New syscalls share the same number across architectures
+pub const SYS_PROCESS_MRELEASE: c_long = 448;
+
+/// What the running kernel supports, probed once at startup
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    pub linux_version: Option<LinuxVersion>,
+    /// `/proc/pressure/memory` can be read
+    pub psi: bool,
+    /// PSI triggers can be registered
+    pub psi_trigger: bool,
+    pub pidfd_open: bool,
+    pub pidfd_send_signal: bool,
+    pub process_mrelease: bool,
+    /// The unified cgroup hierarchy is mounted at `/sys/fs/cgroup`
+    pub cgroup_v2: bool,
+    /// Whole cgroups can be killed through `cgroup.kill`
+    pub cgroup_kill: bool,
+    /// `mlockall` accepts `MCL_ONFAULT`
+    pub mcl_onfault: bool,
+    /// `/proc/<pid>/smaps_rollup` exists (Linux 4.14)
+    pub smaps_rollup: bool,
+}
+
+/// Returns true unless the syscall failed because the kernel doesn't implement it
+fn implemented(ret_val: c_long) -> bool {
+    ret_val >= 0 || errno() != ENOSYS
+}
+
+impl Capabilities {
+    pub fn probe(linux_version: Option<LinuxVersion>, buf: &mut [u8]) -> Self {
+        let psi = linux_version.as_ref().is_none_or(|v| v.supports_psi())
+            && PressureSnapshot::from_file(MEMORY_PRESSURE, buf).is_ok();
+
+        // Unprivileged processes may only use windows that are multiples of 2s
+        let spec = TriggerSpec {
+            kind: PressureKind::Some,
+            stall_us: 150_000,
+            window_us: 2_000_000,
+        };
+        let psi_trigger = psi && PressureTrigger::new(MEMORY_PRESSURE, spec, buf).is_ok();
+
+        // Safety: pidfd_open has no side effects other than returning a file descriptor
+        let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, libc::getpid(), 0) };
+        let pidfd_open = pidfd >= 0;
+
+        let pidfd_send_signal = pidfd_open && {
+            // Safety: signal 0 only checks whether a signal could be sent
+            let ret_val = unsafe {
+                libc::syscall(
+                    libc::SYS_pidfd_send_signal,
+                    pidfd,
+                    0,
+                    ptr::null::<libc::siginfo_t>(),
+                    0,
+                )
+            };
+            ret_val == 0
+        };
+
+        // Safety: an invalid file descriptor fails with EBADF if the syscall exists
+        let process_mrelease = implemented(unsafe { libc::syscall(SYS_PROCESS_MRELEASE, -1, 0) });
+
+        if pidfd_open {
+            // Safety: `pidfd` is a file descriptor we own
+            unsafe { libc::close(pidfd as i32) };
+        }
+
+        let cgroup_v2 = cgroup::is_v2_mounted();
+
+        // The root cgroup has no `cgroup.kill`, so look for it in our own
+        let cgroup_kill = cgroup_v2
+            && match cgroup::own_cgroup(buf) {
+                Ok(Some(own)) if own != "/" => Path::new(&cgroup::dir_of(&own))
+                    .join("cgroup.kill")
+                    .exists(),
+                _ => linux_version
+                    .as_ref()
+                    .is_some_and(|v| v.is_at_least((5, 14))),
+            };
+
+        // There's no way of probing for it without locking memory, but
+        // it's been supported ever since it was defined (Linux 4.4)
+        let mcl_onfault =
+            mcl_onfault() != -1 && linux_version.as_ref().is_none_or(|v| v.is_at_least((4, 4)));
+
+        let smaps_rollup = Path::new("/proc/self/smaps_rollup").exists();
+
+        Self {
+            linux_version,
+            psi,
+            psi_trigger,
+            pidfd_open,
+            pidfd_send_signal,
+            process_mrelease,
+            cgroup_v2,
+            cgroup_kill,
+            mcl_onfault,
+            smaps_rollup,
+        }
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let supported = |supported: bool| if supported { "yes" } else { "no" };
+
+        writeln!(f, "PSI:               {}", supported(self.psi))?;
+        writeln!(f, "PSI triggers:      {}", supported(self.psi_trigger))?;
+        writeln!(f, "pidfd_open:        {}", supported(self.pidfd_open))?;
+        writeln!(
+            f,
+            "pidfd_send_signal: {}",
+            supported(self.pidfd_send_signal)
+        )?;
+        writeln!(f, "process_mrelease:  {}", supported(self.process_mrelease))?;
+        writeln!(f, "cgroup v2:         {}", supported(self.cgroup_v2))?;
+        writeln!(f, "cgroup.kill:       {}", supported(self.cgroup_kill))?;
+        writeln!(f, "MCL_ONFAULT:       {}", supported(self.mcl_onfault))?;
+        write!(f, "smaps_rollup:      {}", supported(self.smaps_rollup))
+    }
+}
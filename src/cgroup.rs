@@ -0,0 +1,336 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::memory::pressure::{PressureCondition, PressureSnapshot};
+use crate::memory::Threshold;
+use crate::utils::{self, str_from_u8};
+
+/// Where the unified (v2) cgroup hierarchy is mounted
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// A cgroup v2 subtree watched separately from the rest of the system.
+///
+/// Configured through a `[cgroup <pattern>]` section, in which `*` and `?`
+/// may be used in any component of the path, e.g. `system.slice/docker-*.scope`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CgroupWatch {
+    pub pattern: String,
+    /// Kill inside the cgroup when its `memory.pressure` meets this condition
+    pub psi_condition: Option<PressureCondition>,
+    /// Kill inside the cgroup when its non-reclaimable usage reaches this limit: a percentage
+    /// of `memory.max`, an absolute size, or whichever of the two is lower
+    pub max_usage: Option<Threshold>,
+    /// After killing inside of a cgroup, leave it alone for this long, in ms, so that
+    /// its pressure averages have time to decay before they are looked at again
+    pub cooldown_ms: u64,
+    /// Kill every process in the cgroup at once through `cgroup.kill`, instead of choosing
+    /// a victim inside of it. Needs Linux 5.14
+    pub kill_cgroup: bool,
+}
+
+impl CgroupWatch {
+    pub fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.trim_matches('/').to_owned(),
+            psi_condition: None,
+            max_usage: None,
+            cooldown_ms: 10_000,
+            kill_cgroup: false,
+        }
+    }
+
+    /// Returns true if the given stats are over any of the limits of this watch
+    pub fn is_exceeded(&self, stats: &CgroupStats) -> bool {
+        let psi_exceeded = self
+            .psi_condition
+            .is_some_and(|condition| condition.is_met(&stats.pressure));
+
+        let usage_exceeded = self
+            .max_usage
+            .is_some_and(|max_usage| stats.is_over(max_usage));
+
+        psi_exceeded || usage_exceeded
+    }
+}
+
+/// Memory readings of a single cgroup
+#[derive(Debug, Default)]
+pub struct CgroupStats {
+    /// `memory.current`, in bytes
+    pub current: u64,
+    /// `memory.max`, in bytes. `None` if the cgroup has no limit
+    pub max: Option<u64>,
+    /// `inactive_file` from `memory.stat`, in bytes: page cache the kernel can reclaim
+    pub inactive_file: u64,
+    /// `memory.pressure`
+    pub pressure: PressureSnapshot,
+}
+
+impl CgroupStats {
+    /// Reads the stats of `cgroup`, given relative to the root of the hierarchy
+    pub fn read(cgroup: &str, buf: &mut [u8]) -> Result<Self> {
+        let dir = dir_of(cgroup);
+
+        let current = read_value(&format!("{}/memory.current", dir), buf)?
+            .ok_or(Error::MalformedCgroupFile)?;
+        let max = read_value(&format!("{}/memory.max", dir), buf)?;
+
+        // Lines look like `inactive_file 4096`, in bytes
+        let mut inactive_file = 0;
+        let file = File::open(format!("{}/memory.stat", dir))?;
+        utils::for_each_line(file, buf, |line| {
+            if let Some(value) = line.strip_prefix("inactive_file ") {
+                inactive_file = value.trim().parse().unwrap_or(0);
+            }
+        })?;
+
+        // Without PSI support, there's no `memory.pressure`. An empty
+        // snapshot never meets any PSI condition, but usage is still checked
+        let pressure = PressureSnapshot::from_file(&format!("{}/memory.pressure", dir), buf)
+            .unwrap_or_default();
+
+        Ok(Self {
+            current,
+            max,
+            inactive_file,
+            pressure,
+        })
+    }
+
+    /// Returns true if usage is at or over `limit`. Usage leaves out the page cache that could
+    /// be reclaimed (much like `MemoryInfo::from_cgroup` does), since cgroups doing I/O
+    /// normally sit near their limit
+    pub fn is_over(&self, limit: Threshold) -> bool {
+        let used_kib = self.current.saturating_sub(self.inactive_file) / 1024;
+
+        let limit_kib = match (limit, self.max.filter(|&max| max > 0)) {
+            (Threshold::Size(kib), _) => kib,
+            (limit, Some(max)) => limit.kib(max / 1024),
+            // Without a `memory.max`, only the size of a `min(..)` is left to go by
+            (Threshold::MinOf(_, kib), None) => kib,
+            (Threshold::Percent(_), None) => return false,
+        };
+
+        used_kib >= limit_kib
+    }
+}
+
+/// Returns the directory of `cgroup`, given relative to the root of the hierarchy
+pub fn dir_of(cgroup: &str) -> String {
+    format!("{}/{}", CGROUP_ROOT, cgroup.trim_start_matches('/'))
+}
+
+/// Kills every process in `cgroup` (and its descendants) by writing to its `cgroup.kill`
+pub fn kill(cgroup: &str) -> Result<()> {
+    fs::write(format!("{}/cgroup.kill", dir_of(cgroup)), "1")?;
+    Ok(())
+}
+
+/// Returns the v2 cgroup `bustd` itself runs in, relative to the root of the hierarchy
+pub fn own_cgroup(buf: &mut [u8]) -> Result<Option<String>> {
+    let file = File::open("/proc/self/cgroup")?;
+
+    let mut own_cgroup = None;
+    utils::for_each_line(file, buf, |line| {
+        if let Some(path) = line.strip_prefix("0::") {
+            own_cgroup = Some(path.to_owned());
+        }
+    })?;
+
+    Ok(own_cgroup)
+}
+
+/// Returns true if the unified (v2) cgroup hierarchy is mounted at `CGROUP_ROOT`
+pub fn is_v2_mounted() -> bool {
+    Path::new(CGROUP_ROOT).join("cgroup.controllers").exists()
+}
+
+/// Returns the cgroup `bustd` runs in if it has a memory limit and is the
+/// root of a cgroup namespace, which is the case when running inside of a container
+pub fn limited_own_cgroup(buf: &mut [u8]) -> Result<Option<String>> {
+    let own = own_cgroup(buf)?;
+    limited_namespace_root(own.as_deref(), CGROUP_ROOT, buf)
+}
+
+/// Returns `own` if it is the root of a cgroup namespace (whose directory is `root`)
+/// with a memory limit. A service with a `MemoryMax=` is limited as well, but `bustd`
+/// must still look after the whole host then, rather than after its own service only
+fn limited_namespace_root(own: Option<&str>, root: &str, buf: &mut [u8]) -> Result<Option<String>> {
+    // Inside of its cgroup namespace, a container sees its own cgroup as `/`
+    if own != Some("/") {
+        return Ok(None);
+    }
+
+    // The root cgroup of the host has no `memory.max`, but the root of a cgroup namespace does
+    let memory_max = format!("{}/memory.max", root);
+    if !Path::new(&memory_max).exists() {
+        return Ok(None);
+    }
+
+    Ok(read_value(&memory_max, buf)?.map(|_| "/".to_owned()))
+}
+
+/// Reads a cgroup file holding a single value, such as `memory.current`.
+/// Returns `None` if the value is `max`
+pub fn read_value(path: &str, buf: &mut [u8]) -> Result<Option<u64>> {
+    let mut file = File::open(path)?;
+    buf.fill(0);
+    let _ = file.read(buf)?;
+
+    match str_from_u8(buf)?.trim() {
+        "max" => Ok(None),
+        value => Ok(Some(value.parse()?)),
+    }
+}
+
+/// Returns every existing cgroup matching `pattern`, relative to the
+/// root of the hierarchy and with a leading slash (e.g. `/user.slice`)
+pub fn expand(pattern: &str) -> Result<Vec<String>> {
+    let mut matches = vec![String::new()];
+
+    for component in pattern.split('/').filter(|c| !c.is_empty()) {
+        let mut next_matches = Vec::new();
+
+        for parent in &matches {
+            let parent_dir = format!("{}{}", CGROUP_ROOT, parent);
+
+            if !component.contains(['*', '?']) {
+                if Path::new(&parent_dir).join(component).is_dir() {
+                    next_matches.push(format!("{}/{}", parent, component));
+                }
+                continue;
+            }
+
+            let entries = match fs::read_dir(&parent_dir) {
+                Ok(entries) => entries,
+                // The cgroup may have been removed in the meantime
+                Err(_) => continue,
+            };
+
+            for entry in entries.filter_map(|e| e.ok()) {
+                let is_dir = entry.file_type().is_ok_and(|ty| ty.is_dir());
+                let name = entry.file_name();
+                let name = match name.to_str() {
+                    Some(name) if is_dir => name,
+                    _ => continue,
+                };
+
+                if utils::wildcard_match(component, name) {
+                    next_matches.push(format!("{}/{}", parent, name));
+                }
+            }
+        }
+
+        matches = next_matches;
+    }
+
+    // An empty pattern would otherwise match the root itself
+    matches.retain(|cgroup| !cgroup.is_empty());
+    matches.sort();
+
+    Ok(matches)
+}
+
+/// Returns true if the cgroup `path` is `ancestor` or one of its descendants
+pub fn is_descendant(path: &str, ancestor: &str) -> bool {
+    let ancestor = ancestor.trim_end_matches('/');
+
+    match path.strip_prefix(ancestor) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || ancestor.is_empty(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{is_descendant, limited_namespace_root, CgroupStats, CgroupWatch};
+    use crate::memory::pressure::PressureSnapshot;
+    use crate::memory::Threshold;
+
+    #[test]
+    fn namespace_roots() {
+        let root = std::env::temp_dir().join(format!("bustd-cgroup-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let root_str = root.to_str().unwrap();
+        let mut buf = [0_u8; 64];
+
+        // The host's root cgroup, without a limit
+        assert_eq!(
+            limited_namespace_root(Some("/"), root_str, &mut buf).unwrap(),
+            None
+        );
+
+        fs::write(root.join("memory.max"), "max\n").unwrap();
+        assert_eq!(
+            limited_namespace_root(Some("/"), root_str, &mut buf).unwrap(),
+            None
+        );
+
+        // A container
+        fs::write(root.join("memory.max"), "1073741824\n").unwrap();
+        assert_eq!(
+            limited_namespace_root(Some("/"), root_str, &mut buf).unwrap(),
+            Some("/".to_owned())
+        );
+
+        // A service with a `MemoryMax=`, or no unified hierarchy at all
+        let service = Some("/system.slice/bustd.service");
+        assert_eq!(
+            limited_namespace_root(service, root_str, &mut buf).unwrap(),
+            None
+        );
+        assert_eq!(
+            limited_namespace_root(None, root_str, &mut buf).unwrap(),
+            None
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn exceeded() {
+        const GIB: u64 = 1024 * 1024 * 1024;
+
+        let mut watch = CgroupWatch::new("/build.slice/");
+        assert_eq!(watch.pattern, "build.slice");
+        watch.max_usage = Some(Threshold::Percent(95.0));
+
+        // Mostly page cache, which doesn't count
+        let mut stats = CgroupStats {
+            current: 99 * GIB / 100,
+            max: Some(GIB),
+            inactive_file: GIB / 2,
+            pressure: PressureSnapshot::default(),
+        };
+        assert!(!watch.is_exceeded(&stats));
+
+        stats.inactive_file = GIB / 100;
+        assert!(watch.is_exceeded(&stats));
+
+        // Without a limit, a percentage can't be exceeded, but a size can
+        stats.max = None;
+        assert!(!watch.is_exceeded(&stats));
+        watch.max_usage = Some("min(95%, 512M)".parse().unwrap());
+        assert!(watch.is_exceeded(&stats));
+        watch.max_usage = Some(Threshold::Size(GIB / 1024));
+        assert!(!watch.is_exceeded(&stats));
+
+        watch.psi_condition = Some("full avg10 > 20".parse().unwrap());
+        assert!(!watch.is_exceeded(&stats));
+        stats.pressure.full.avg10 = 25.0;
+        assert!(watch.is_exceeded(&stats));
+    }
+
+    #[test]
+    fn descendants() {
+        assert!(is_descendant("/user.slice", "/user.slice"));
+        assert!(is_descendant("/user.slice/user-1000.slice", "/user.slice"));
+        assert!(is_descendant("/user.slice/user-1000.slice", "/"));
+        assert!(!is_descendant("/user.slice-other", "/user.slice"));
+        assert!(!is_descendant("/system.slice", "/user.slice"));
+    }
+}
@@ -1,8 +1,19 @@
 use argh::FromArgs;
 
+use crate::config::Combine;
+use crate::memory::{
+    pressure::{PressureCondition, TriggerSpec},
+    MemorySource, Threshold,
+};
+use crate::policy::PolicyKind;
+use crate::rule::Rule;
+
 #[derive(FromArgs)]
 /// Lightweight process killer daemon for out-of-memory scenarios
 pub struct CommandLineArgs {
+    #[argh(subcommand)]
+    pub command: Option<Command>,
+
     /// toggles on verbose output
     #[argh(switch, short = 'V')]
     pub verbose: bool,
@@ -11,26 +22,188 @@ pub struct CommandLineArgs {
     #[argh(switch, short = 'n')]
     pub no_daemon: bool,
 
+    /// take a single reading, act on it if needed and exit with 0 (okay), 1 (warning), 2 (critical, acted), 3 (critical, failed to act) or 4 (could not check)
+    #[argh(switch)]
+    pub once: bool,
+
     /// when set, the victim's entire process group will be killed
     #[argh(switch, short = 'g')]
     pub kill_pgroup: bool,
 
-    /// sets the PSI value on which, if surpassed, a process will be killed
-    #[argh(option, short = 'p', long = "psi", default = "25.0")]
-    pub cutoff_psi: f32, // TODO: responsitivity multiplier?
+    /// when set, bustd chooses victims but only logs what it would have sent them instead of killing
+    #[argh(switch, long = "dry-run")]
+    pub dry_run: bool,
+
+    /// when set, bustd monitors the whole host even when running inside of a memory-limited cgroup
+    #[argh(switch)]
+    pub ignore_container: bool,
+
+    /// path to the configuration file (defaults to /etc/bustd/bustd.conf)
+    #[argh(option, short = 'c')]
+    pub config: Option<String>,
+
+    /// where to read available memory from: `sysinfo` (fast, default) or `meminfo` (accounts for reclaimable cache)
+    #[argh(option, long = "memory-source")]
+    pub memory_source: Option<MemorySource>,
+
+    /// sets the PSI value on which, if surpassed, a process will be killed (defaults to 25.0)
+    #[argh(option, short = 'p', long = "psi")]
+    pub cutoff_psi: Option<f32>, // TODO: responsitivity multiplier?
+
+    /// sets the stall percentage over the last --psi-window ms which, if surpassed, makes a process be killed. Used in place of --psi
+    #[argh(option, long = "psi-window-cutoff")]
+    pub psi_window_cutoff: Option<f32>,
+
+    /// length of the window used by --psi-window-cutoff and `window` PSI conditions, in ms (defaults to 1000)
+    #[argh(option, long = "psi-window")]
+    pub psi_window_ms: Option<u64>,
+
+    /// a PSI condition such as "full avg10 > 10" or "some window >= 40" which, if met, makes a process be killed. Takes precedence over --psi
+    #[argh(option, long = "psi-condition")]
+    pub psi_condition: Option<PressureCondition>,
+
+    /// a PSI trigger such as "some 150000 1000000": sleep until the memory stall time exceeds 150ms in a 1s window instead of polling
+    #[argh(option, long = "psi-trigger")]
+    pub psi_trigger: Option<TriggerSpec>,
+
+    /// while memory is fine, how long to wait for the PSI trigger before reading memory again anyway, in ms. 60000 by default
+    #[argh(option, long = "psi-trigger-timeout")]
+    pub psi_trigger_timeout_ms: Option<u64>,
+
+    /// only act once memory has been low for this many consecutive readings
+    #[argh(option, long = "sustain-samples")]
+    pub sustain_samples: Option<u32>,
+
+    /// only act once memory has been low for this many ms
+    #[argh(option, long = "sustain-ms")]
+    pub sustain_ms: Option<u64>,
+
+    /// once acting, keep doing so until the PSI value falls below this (defaults to the PSI cutoff)
+    #[argh(option, long = "clear-psi")]
+    pub clear_psi: Option<f32>,
+
+    /// kill when available RAM is at or below this percentage or size, e.g. "5%", "512M" or "min(5%, 2G)" (and so is swap, if -s is given)
+    #[argh(option, short = 'm', long = "kill-ram")]
+    pub kill_ram: Option<Threshold>,
+
+    /// kill when available swap is at or below this percentage or size (and so is RAM, if -m is given)
+    #[argh(option, short = 's', long = "kill-swap")]
+    pub kill_swap: Option<Threshold>,
+
+    /// whether to kill when `any` of the PSI condition and the -m/-s thresholds is met (default), or only when `all` of them are
+    #[argh(option)]
+    pub combine: Option<Combine>,
+
+    /// how victims are chosen: by `oom_score` (default), largest `rss`, `anon_swap`, `pss` or `uss`, `newest` or `fastest_grower`
+    #[argh(option, long = "victim-policy")]
+    pub victim_policy: Option<PolicyKind>,
+
+    /// how many of the processes with the largest RSS the `pss` and `uss` victim policies read smaps_rollup for (defaults to 8)
+    #[argh(option, long = "smaps-candidates")]
+    pub smaps_candidates: Option<usize>,
+
+    /// warn, without killing, when available RAM is at or below this percentage or size
+    #[argh(option, long = "warn-ram")]
+    pub warn_ram: Option<Threshold>,
+
+    /// warn, without killing, when the PSI value surpasses this
+    #[argh(option, long = "warn-psi")]
+    pub warn_psi: Option<f32>,
+
+    /// warn, without killing, when this PSI condition is met. Takes precedence over --warn-psi
+    #[argh(option, long = "warn-psi-condition")]
+    pub warn_psi_condition: Option<PressureCondition>,
+
+    /// show a desktop notification through notify-send when warning
+    #[argh(switch, long = "warn-notify")]
+    pub warn_notify: bool,
+
+    /// shell command run when warning
+    #[argh(option, long = "warn-hook")]
+    pub warn_hook: Option<String>,
+
+    /// minimum time between two warnings, in ms (defaults to 60000)
+    #[argh(option, long = "warn-interval")]
+    pub warn_interval_ms: Option<u64>,
+
+    /// kill when the recent trend projects RAM and swap to run out within this many ms
+    #[argh(option, long = "forecast-horizon")]
+    pub forecast_horizon_ms: Option<u64>,
+
+    /// available RAM (percentage or size) below which PSI starts being checked (defaults to 15%)
+    #[argh(option, long = "near-terminal")]
+    pub near_terminal: Option<Threshold>,
+
+    /// available RAM (percentage or size) considered terminal by the adaptive sleep (defaults to 10%)
+    #[argh(option, long = "ram-terminal")]
+    pub ram_terminal: Option<Threshold>,
+
+    /// available swap (percentage or size) considered terminal by the adaptive sleep (defaults to 10%)
+    #[argh(option, long = "swap-terminal")]
+    pub swap_terminal: Option<Threshold>,
+
+    /// maximum expected RAM fill rate in KiB/ms, used by the adaptive sleep until the actual rate is measured (defaults to 6000)
+    #[argh(option, long = "ram-fill-rate")]
+    pub ram_fill_rate: Option<i64>,
+
+    /// maximum expected swap fill rate in KiB/ms, used by the adaptive sleep until the actual rate is measured (defaults to 800)
+    #[argh(option, long = "swap-fill-rate")]
+    pub swap_fill_rate: Option<i64>,
+
+    /// minimum time to sleep between memory readings, in ms (defaults to 100)
+    #[argh(option, long = "min-sleep")]
+    pub min_sleep_ms: Option<u64>,
+
+    /// maximum time to sleep between memory readings, in ms (defaults to 1000)
+    #[argh(option, long = "max-sleep")]
+    pub max_sleep_ms: Option<u64>,
+
+    /// tilde-separated rules such as "chrome" or "cmdline:*language-server*": processes matching any of them are killed before all others
+    #[argh(option, from_str_fn(parse_rules))]
+    pub prefer: Option<Vec<Rule>>,
+
+    /// tilde-separated rules: processes matching any of them are only killed once no other process is left
+    #[argh(option, from_str_fn(parse_rules))]
+    pub avoid: Option<Vec<Rule>>,
+
+    /// tilde-separated rules such as "user:postgres" or "exe:/usr/sbin/*": processes matching any of them are never killed
+    #[argh(option, from_str_fn(parse_rules))]
+    pub protect: Option<Vec<Rule>>,
 
     #[cfg(feature = "glob-ignore")]
     /// all processes whose names match any of the supplied tilde-separated glob patterns will never be chosen to be killed
-    #[argh(
-        option,
-        short = 'u',
-        long = "unkillables",
-        from_str_fn(parse_unkillables)
-    )]
-    pub ignored: Option<Vec<String>>,
+    #[argh(option, short = 'u', long = "unkillables", from_str_fn(parse_patterns))]
+    pub ignored: Option<Vec<glob::Pattern>>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum Command {
+    Doctor(DoctorArgs),
+    Explain(ExplainArgs),
+}
+
+#[derive(FromArgs)]
+/// Checks whether bustd can run properly on this host, exiting with 1 if it can't
+#[argh(subcommand, name = "doctor")]
+pub struct DoctorArgs {}
+
+#[derive(FromArgs)]
+/// Lists the processes bustd would kill right now, in order, and the ones it would never kill
+#[argh(subcommand, name = "explain")]
+pub struct ExplainArgs {
+    /// print the listing as JSON
+    #[argh(switch)]
+    pub json: bool,
+}
+
+fn parse_rules(arg: &str) -> Result<Vec<Rule>, String> {
+    arg.split('~').map(|rule| rule.trim().parse()).collect()
 }
 
 #[cfg(feature = "glob-ignore")]
-fn parse_unkillables(arg: &str) -> Result<Vec<String>, String> {
-    Ok(arg.split('~').map(ToOwned::to_owned).collect())
+fn parse_patterns(arg: &str) -> Result<Vec<glob::Pattern>, String> {
+    arg.split('~')
+        .map(|pattern| glob::Pattern::new(pattern).map_err(|err| err.to_string()))
+        .collect()
 }
@@ -0,0 +1,706 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::{fmt, str::FromStr};
+
+use crate::cgroup::CgroupWatch;
+use crate::cli::CommandLineArgs;
+use crate::error::{Error, Result};
+use crate::memory::pressure::{PressureCondition, PressureField, TriggerSpec};
+use crate::memory::{MemorySource, Threshold};
+use crate::policy::PolicyKind;
+use crate::rule::Rule;
+use crate::tier::Tiers;
+
+/// The configuration file read when no `--config` is supplied
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/bustd/bustd.conf";
+
+/// Directory, relative to the folder of the main configuration file,
+/// in which drop-in files (`*.conf`) are looked for
+const DROP_IN_DIR: &str = "conf.d";
+
+/// How the PSI condition is combined with the RAM and swap thresholds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Combine {
+    /// Kill when either the PSI condition or the thresholds are met
+    Any,
+    /// Kill only when both the PSI condition and the thresholds are met
+    All,
+}
+
+impl FromStr for Combine {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(Self::Any),
+            "all" => Ok(Self::All),
+            _ => Err(format!("expected `any` or `all`, found `{}`", s)),
+        }
+    }
+}
+
+/// Every setting that affects how `bustd` monitors the system.
+///
+/// A `Config` starts out with the default values, is then updated with whatever is found in
+/// the configuration file and its drop-ins (in lexicographical order) and is finally
+/// overridden by the command-line arguments.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub verbose: bool,
+    pub kill_pgroup: bool,
+    /// Go through the motions of killing, but never actually send any signal
+    pub dry_run: bool,
+    /// Where to read available memory from
+    pub memory_source: MemorySource,
+    /// If `bustd` runs inside of a cgroup with a memory limit (e.g. in a container),
+    /// monitor that cgroup and only kill processes inside of it
+    pub container_aware: bool,
+    /// The PSI condition which, if met, makes a process be killed.
+    /// Set either directly or through `cutoff_psi`, which means `some avg10 >= cutoff_psi`
+    pub psi_condition: PressureCondition,
+    /// Length of the window used for `window` PSI conditions, in ms
+    pub psi_window_ms: u64,
+    /// When set, `bustd` registers this PSI trigger and sleeps until it fires
+    /// instead of polling at fixed intervals
+    pub psi_trigger: Option<TriggerSpec>,
+    /// While memory is fine and the PSI trigger is registered, memory is read again
+    /// after this long even if the trigger didn't fire, in ms
+    pub psi_trigger_timeout_ms: u64,
+    /// Memory must stay low for this many consecutive readings before acting
+    pub sustain_samples: Option<u32>,
+    /// Memory must stay low for this long before acting, in ms
+    pub sustain_ms: Option<u64>,
+    /// Once acting, keep doing so until the value of the PSI condition falls below this.
+    /// Defaults to the threshold of the PSI condition itself
+    pub clear_psi: Option<f32>,
+    /// Kill when available RAM is at or below this threshold (and so is
+    /// swap, if `kill_swap` is set)
+    pub kill_ram: Option<Threshold>,
+    /// Kill when available swap is at or below this threshold (and so is
+    /// RAM, if `kill_ram` is set). Systems without swap always meet it
+    pub kill_swap: Option<Threshold>,
+    /// How the PSI condition and the RAM/swap thresholds are combined
+    pub combine: Combine,
+    /// Warn, without killing, when available RAM is at or below this threshold
+    pub warn_ram: Option<Threshold>,
+    /// Warn, without killing, when this PSI condition is met.
+    /// Set either directly or through `warn_psi`, which means `some avg10 >= warn_psi`
+    pub warn_psi_condition: Option<PressureCondition>,
+    /// Show a desktop notification (through `notify-send`) when warning
+    pub warn_notify: bool,
+    /// Shell command run when warning
+    pub warn_hook: Option<String>,
+    /// Minimum time between two warnings, in ms
+    pub warn_interval_ms: u64,
+    /// Act when RAM and swap are projected to run out within this many ms
+    pub forecast_horizon_ms: Option<u64>,
+    /// Below this much available RAM, PSI starts being checked
+    pub near_terminal: Threshold,
+    /// Available RAM considered terminal by the adaptive sleep
+    pub ram_terminal: Threshold,
+    /// Available swap considered terminal by the adaptive sleep
+    pub swap_terminal: Threshold,
+    /// Maximum expected RAM fill rate, in KiB per ms. Used by the
+    /// adaptive sleep until the actual fill rate can be measured
+    pub ram_fill_rate: i64,
+    /// Maximum expected swap fill rate, in KiB per ms. Used by the
+    /// adaptive sleep until the actual fill rate can be measured
+    pub swap_fill_rate: i64,
+    /// Minimum time to sleep between memory readings, in ms
+    pub min_sleep_ms: u64,
+    /// Maximum time to sleep between memory readings, in ms
+    pub max_sleep_ms: u64,
+    /// cgroup v2 subtrees watched separately, each with its own limits
+    pub cgroups: Vec<CgroupWatch>,
+    /// How victims are chosen
+    pub victim_policy: PolicyKind,
+    /// How many of the candidates with the largest RSS are scored by
+    /// expensive victim policies, i.e. those reading `smaps_rollup`
+    pub smaps_candidates: usize,
+    /// Which processes are killed first, last or never
+    pub tiers: Tiers,
+    #[cfg(feature = "glob-ignore")]
+    pub ignored: Option<Vec<glob::Pattern>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            verbose: false,
+            kill_pgroup: false,
+            dry_run: false,
+            memory_source: MemorySource::Sysinfo,
+            container_aware: true,
+            psi_condition: PressureCondition::some_avg10(25.0),
+            psi_window_ms: 1000,
+            psi_trigger: None,
+            psi_trigger_timeout_ms: 60_000,
+            sustain_samples: None,
+            sustain_ms: None,
+            clear_psi: None,
+            kill_ram: None,
+            kill_swap: None,
+            combine: Combine::Any,
+            forecast_horizon_ms: None,
+            warn_ram: None,
+            warn_psi_condition: None,
+            warn_notify: false,
+            warn_hook: None,
+            warn_interval_ms: 60_000,
+            near_terminal: Threshold::Percent(15.),
+            ram_terminal: Threshold::Percent(10.),
+            swap_terminal: Threshold::Percent(10.),
+            // Maximum expected memory fill rate as seen
+            // with `stress -m 4 --vm-bytes 4G`
+            ram_fill_rate: 6000,
+            // Maximum expected swap fill rate as seen
+            // with membomb on zRAM
+            swap_fill_rate: 800,
+            min_sleep_ms: 100,
+            max_sleep_ms: 1000,
+            cgroups: Vec::new(),
+            victim_policy: PolicyKind::OomScore,
+            smaps_candidates: 8,
+            tiers: Tiers::default(),
+            #[cfg(feature = "glob-ignore")]
+            ignored: None,
+        }
+    }
+}
+
+macro_rules! invalid {
+    ($($arg:tt)*) => {
+        Error::InvalidConfig {
+            reason: format!($($arg)*),
+        }
+    };
+}
+
+fn parse_value<T>(key: &str, value: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|err| invalid!("invalid value `{}` for `{}`: {}", value, key, err))
+}
+
+/// Parses a tilde-separated list of glob patterns
+#[cfg(feature = "glob-ignore")]
+fn parse_patterns(key: &str, value: &str) -> Result<Vec<glob::Pattern>> {
+    value
+        .split('~')
+        .map(|pattern| parse_value(key, pattern.trim()))
+        .collect()
+}
+
+/// Parses a tilde-separated list of rules
+fn parse_rules(key: &str, value: &str) -> Result<Vec<Rule>> {
+    value
+        .split('~')
+        .map(|rule| parse_value(key, rule.trim()))
+        .collect()
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    match value {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(invalid!("invalid value `{}` for `{}`", value, key)),
+    }
+}
+
+impl Config {
+    /// Builds the configuration from the configuration file, its drop-ins and the
+    /// command-line arguments, in that order of precedence (lowest to highest).
+    pub fn load(args: &CommandLineArgs) -> Result<Self> {
+        let mut config = Self::default();
+
+        let path = args
+            .config
+            .as_deref()
+            .map(Path::new)
+            .unwrap_or_else(|| Path::new(DEFAULT_CONFIG_PATH));
+
+        // The default configuration file is optional, but one explicitly
+        // given through the command line must exist
+        if args.config.is_some() || path.exists() {
+            config.apply_file(path)?;
+        }
+
+        if let Some(drop_in_dir) = path.parent().map(|dir| dir.join(DROP_IN_DIR)) {
+            for drop_in in drop_ins(&drop_in_dir)? {
+                config.apply_file(&drop_in)?;
+            }
+        }
+
+        config.apply_args(args);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn apply_file(&mut self, path: &Path) -> Result<()> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| invalid!("could not read {}: {}", path.display(), err))?;
+
+        self.apply_str(&contents)
+            .map_err(|err| invalid!("{}: {}", path.display(), err))
+    }
+
+    /// Applies the contents of a configuration file.
+    ///
+    /// The format is a list of `key = value` lines. Empty lines and
+    /// everything following a `#` are ignored.
+    ///
+    /// A `[cgroup <pattern>]` line starts a section in which the following
+    /// keys apply to the cgroups matching `<pattern>` instead.
+    fn apply_str(&mut self, contents: &str) -> Result<()> {
+        // Index of the cgroup watch whose section we're in, if any
+        let mut cgroup_section = None;
+
+        for (idx, line) in contents.lines().enumerate() {
+            let line = match line.find('#') {
+                Some(comment_start) => &line[..comment_start],
+                None => line,
+            }
+            .trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let pattern = header
+                    .strip_prefix("cgroup ")
+                    .map(str::trim)
+                    .filter(|pattern| !pattern.is_empty())
+                    .ok_or_else(|| invalid!("line {}: expected `[cgroup <pattern>]`", idx + 1))?;
+
+                self.cgroups.push(CgroupWatch::new(pattern));
+                cgroup_section = Some(self.cgroups.len() - 1);
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid!("line {}: expected `key = value`", idx + 1))?;
+            let (key, value) = (key.trim(), value.trim());
+
+            match cgroup_section {
+                Some(watch_idx) => set_cgroup(&mut self.cgroups[watch_idx], key, value),
+                None => self.set(key, value),
+            }
+            .map_err(|err| invalid!("line {}: {}", idx + 1, err))?;
+        }
+
+        Ok(())
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "verbose" => self.verbose = parse_bool(key, value)?,
+            "kill_pgroup" => self.kill_pgroup = parse_bool(key, value)?,
+            "dry_run" => self.dry_run = parse_bool(key, value)?,
+            "memory_source" => self.memory_source = parse_value(key, value)?,
+            "container_aware" => self.container_aware = parse_bool(key, value)?,
+            "cutoff_psi" => {
+                self.psi_condition = PressureCondition::some_avg10(parse_value(key, value)?)
+            }
+            "psi_condition" => self.psi_condition = parse_value(key, value)?,
+            "psi_window_ms" => self.psi_window_ms = parse_value(key, value)?,
+            "psi_trigger" => self.psi_trigger = Some(parse_value(key, value)?),
+            "psi_trigger_timeout_ms" => self.psi_trigger_timeout_ms = parse_value(key, value)?,
+            "sustain_samples" => self.sustain_samples = Some(parse_value(key, value)?),
+            "sustain_ms" => self.sustain_ms = Some(parse_value(key, value)?),
+            "clear_psi" => self.clear_psi = Some(parse_value(key, value)?),
+            // The `_percent` keys are kept for compatibility. Any threshold can be either
+            // a percentage or a size (as in `512M`) regardless of the name of its key
+            "kill_ram" | "kill_ram_percent" => self.kill_ram = Some(parse_value(key, value)?),
+            "kill_swap" | "kill_swap_percent" => self.kill_swap = Some(parse_value(key, value)?),
+            "combine" => self.combine = parse_value(key, value)?,
+            "warn_ram" => self.warn_ram = Some(parse_value(key, value)?),
+            "warn_psi" => {
+                self.warn_psi_condition =
+                    Some(PressureCondition::some_avg10(parse_value(key, value)?))
+            }
+            "warn_psi_condition" => self.warn_psi_condition = Some(parse_value(key, value)?),
+            "warn_notify" => self.warn_notify = parse_bool(key, value)?,
+            "warn_hook" => self.warn_hook = Some(value.to_owned()),
+            "warn_interval_ms" => self.warn_interval_ms = parse_value(key, value)?,
+            "forecast_horizon_ms" => self.forecast_horizon_ms = Some(parse_value(key, value)?),
+            "near_terminal" | "near_terminal_percent" => {
+                self.near_terminal = parse_value(key, value)?
+            }
+            "ram_terminal" | "ram_terminal_percent" => self.ram_terminal = parse_value(key, value)?,
+            "swap_terminal" | "swap_terminal_percent" => {
+                self.swap_terminal = parse_value(key, value)?
+            }
+            "ram_fill_rate" => self.ram_fill_rate = parse_value(key, value)?,
+            "swap_fill_rate" => self.swap_fill_rate = parse_value(key, value)?,
+            "min_sleep_ms" => self.min_sleep_ms = parse_value(key, value)?,
+            "max_sleep_ms" => self.max_sleep_ms = parse_value(key, value)?,
+            "victim_policy" => self.victim_policy = parse_value(key, value)?,
+            "smaps_candidates" => self.smaps_candidates = parse_value(key, value)?,
+            "prefer" => self.tiers.prefer = parse_rules(key, value)?,
+            "avoid" => self.tiers.avoid = parse_rules(key, value)?,
+            "protect" => self.tiers.protect = parse_rules(key, value)?,
+            #[cfg(feature = "glob-ignore")]
+            "unkillables" => self.ignored = Some(parse_patterns(key, value)?),
+            _ => return Err(invalid!("unknown key `{}`", key)),
+        }
+
+        Ok(())
+    }
+
+    fn apply_args(&mut self, args: &CommandLineArgs) {
+        self.verbose |= args.verbose;
+        self.kill_pgroup |= args.kill_pgroup;
+        self.dry_run |= args.dry_run;
+
+        if args.ignore_container {
+            self.container_aware = false;
+        }
+        if let Some(memory_source) = args.memory_source {
+            self.memory_source = memory_source;
+        }
+        if let Some(cutoff_psi) = args.cutoff_psi {
+            self.psi_condition = PressureCondition::some_avg10(cutoff_psi);
+        }
+        if let Some(cutoff) = args.psi_window_cutoff {
+            self.psi_condition = PressureCondition {
+                field: PressureField::Window,
+                ..PressureCondition::some_avg10(cutoff)
+            };
+        }
+        if let Some(psi_condition) = args.psi_condition {
+            self.psi_condition = psi_condition;
+        }
+        if let Some(psi_window_ms) = args.psi_window_ms {
+            self.psi_window_ms = psi_window_ms;
+        }
+        if let Some(psi_trigger) = args.psi_trigger {
+            self.psi_trigger = Some(psi_trigger);
+        }
+        if let Some(psi_trigger_timeout_ms) = args.psi_trigger_timeout_ms {
+            self.psi_trigger_timeout_ms = psi_trigger_timeout_ms;
+        }
+        if let Some(sustain_samples) = args.sustain_samples {
+            self.sustain_samples = Some(sustain_samples);
+        }
+        if let Some(sustain_ms) = args.sustain_ms {
+            self.sustain_ms = Some(sustain_ms);
+        }
+        if let Some(clear_psi) = args.clear_psi {
+            self.clear_psi = Some(clear_psi);
+        }
+        if let Some(kill_ram) = args.kill_ram {
+            self.kill_ram = Some(kill_ram);
+        }
+        if let Some(kill_swap) = args.kill_swap {
+            self.kill_swap = Some(kill_swap);
+        }
+        if let Some(combine) = args.combine {
+            self.combine = combine;
+        }
+        self.warn_notify |= args.warn_notify;
+
+        if let Some(warn_ram) = args.warn_ram {
+            self.warn_ram = Some(warn_ram);
+        }
+        if let Some(warn_psi) = args.warn_psi {
+            self.warn_psi_condition = Some(PressureCondition::some_avg10(warn_psi));
+        }
+        if let Some(warn_psi_condition) = args.warn_psi_condition {
+            self.warn_psi_condition = Some(warn_psi_condition);
+        }
+        if let Some(warn_hook) = &args.warn_hook {
+            self.warn_hook = Some(warn_hook.clone());
+        }
+        if let Some(warn_interval_ms) = args.warn_interval_ms {
+            self.warn_interval_ms = warn_interval_ms;
+        }
+        if let Some(forecast_horizon_ms) = args.forecast_horizon_ms {
+            self.forecast_horizon_ms = Some(forecast_horizon_ms);
+        }
+        if let Some(near_terminal) = args.near_terminal {
+            self.near_terminal = near_terminal;
+        }
+        if let Some(ram_terminal) = args.ram_terminal {
+            self.ram_terminal = ram_terminal;
+        }
+        if let Some(swap_terminal) = args.swap_terminal {
+            self.swap_terminal = swap_terminal;
+        }
+        if let Some(ram_fill_rate) = args.ram_fill_rate {
+            self.ram_fill_rate = ram_fill_rate;
+        }
+        if let Some(swap_fill_rate) = args.swap_fill_rate {
+            self.swap_fill_rate = swap_fill_rate;
+        }
+        if let Some(min_sleep_ms) = args.min_sleep_ms {
+            self.min_sleep_ms = min_sleep_ms;
+        }
+        if let Some(max_sleep_ms) = args.max_sleep_ms {
+            self.max_sleep_ms = max_sleep_ms;
+        }
+        if let Some(victim_policy) = args.victim_policy {
+            self.victim_policy = victim_policy;
+        }
+        if let Some(smaps_candidates) = args.smaps_candidates {
+            self.smaps_candidates = smaps_candidates;
+        }
+        if let Some(prefer) = &args.prefer {
+            self.tiers.prefer = prefer.clone();
+        }
+        if let Some(avoid) = &args.avoid {
+            self.tiers.avoid = avoid.clone();
+        }
+        if let Some(protect) = &args.protect {
+            self.tiers.protect = protect.clone();
+        }
+
+        #[cfg(feature = "glob-ignore")]
+        if let Some(ignored) = &args.ignored {
+            self.ignored = Some(ignored.clone());
+        }
+    }
+
+    /// Returns true if any check doesn't depend on PSI, and so can't fire a PSI trigger:
+    /// the RAM and swap thresholds, the forecast and the cgroup watches
+    pub fn has_non_psi_checks(&self) -> bool {
+        self.kill_ram.is_some()
+            || self.kill_swap.is_some()
+            || self.warn_ram.is_some()
+            || self.forecast_horizon_ms.is_some()
+            || !self.cgroups.is_empty()
+    }
+
+    fn validate(&self) -> Result<()> {
+        let is_percent = |value: f64| (0.0..=100.0).contains(&value);
+
+        if !is_percent(self.psi_condition.threshold.into()) {
+            return Err(invalid!("the PSI threshold must be between 0 and 100"));
+        }
+        if self
+            .warn_psi_condition
+            .is_some_and(|condition| !is_percent(condition.threshold.into()))
+        {
+            return Err(invalid!(
+                "the warning PSI threshold must be between 0 and 100"
+            ));
+        }
+        if self
+            .clear_psi
+            .is_some_and(|clear| !(0.0..=self.psi_condition.threshold).contains(&clear))
+        {
+            return Err(invalid!(
+                "`clear_psi` must be between 0 and the threshold of the PSI condition"
+            ));
+        }
+        if self.sustain_samples == Some(0) {
+            return Err(invalid!("`sustain_samples` must be greater than zero"));
+        }
+        if self.smaps_candidates == 0 {
+            return Err(invalid!("`smaps_candidates` must be greater than zero"));
+        }
+        if self.ram_fill_rate <= 0 || self.swap_fill_rate <= 0 {
+            return Err(invalid!("fill rates must be greater than zero"));
+        }
+        if self.psi_window_ms == 0 {
+            return Err(invalid!("`psi_window_ms` must be greater than zero"));
+        }
+        if self.min_sleep_ms == 0 {
+            return Err(invalid!("`min_sleep_ms` must be greater than zero"));
+        }
+        if self.min_sleep_ms > self.max_sleep_ms {
+            return Err(invalid!("`min_sleep_ms` must not exceed `max_sleep_ms`"));
+        }
+        if self.psi_trigger.is_some() && self.psi_trigger_timeout_ms < self.max_sleep_ms {
+            return Err(invalid!(
+                "`psi_trigger_timeout_ms` must not be shorter than `max_sleep_ms`"
+            ));
+        }
+
+        for watch in &self.cgroups {
+            if watch.psi_condition.is_none() && watch.max_usage.is_none() {
+                return Err(invalid!("cgroup `{}` has no limits set", watch.pattern));
+            }
+            // Windowed pressure is only tracked system-wide
+            if matches!(watch.psi_condition, Some(c) if c.field == PressureField::Window) {
+                return Err(invalid!(
+                    "cgroup `{}`: `window` conditions are not supported for cgroups",
+                    watch.pattern
+                ));
+            }
+            if let Some(Threshold::Percent(usage) | Threshold::MinOf(usage, _)) = watch.max_usage {
+                if !is_percent(usage) {
+                    return Err(invalid!(
+                        "cgroup `{}`: `max_usage` must be between 0 and 100%",
+                        watch.pattern
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn set_cgroup(watch: &mut CgroupWatch, key: &str, value: &str) -> Result<()> {
+    match key {
+        "psi_condition" => watch.psi_condition = Some(parse_value(key, value)?),
+        "max_usage" => watch.max_usage = Some(parse_value(key, value)?),
+        // Kept from before `max_usage` also took sizes
+        "max_usage_percent" => watch.max_usage = Some(Threshold::Percent(parse_value(key, value)?)),
+        "cooldown_ms" => watch.cooldown_ms = parse_value(key, value)?,
+        "kill_cgroup" => watch.kill_cgroup = parse_bool(key, value)?,
+        _ => return Err(invalid!("unknown cgroup key `{}`", key)),
+    }
+
+    Ok(())
+}
+
+/// Returns the `*.conf` files of the given directory, sorted by name
+fn drop_ins(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut drop_ins: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "conf"))
+        .collect();
+
+    drop_ins.sort();
+
+    Ok(drop_ins)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Combine, Config};
+    use crate::memory::pressure::{PressureCondition, PressureKind, TriggerSpec};
+    use crate::memory::{MemorySource, Threshold};
+    use crate::policy::PolicyKind;
+
+    #[test]
+    fn parses_keys_and_comments() {
+        let mut config = Config::default();
+        config
+            .apply_str(
+                "# Thresholds for our build boxes\n\
+                 cutoff_psi = 40.5\n\
+                 \n\
+                 near_terminal_percent = 20 # trailing comment\n\
+                 kill_ram = min(5%, 2G)\n\
+                 max_sleep_ms=2000\n\
+                 kill_pgroup = yes\n\
+                 memory_source = meminfo\n\
+                 psi_trigger = full 150000 1000000\n\
+                 kill_swap_percent = 5\n\
+                 combine = all\n\
+                 victim_policy = anon_swap\n\
+                 prefer = chrom* ~ cmdline:*-language-server*\n",
+            )
+            .unwrap();
+
+        config.validate().unwrap();
+        assert!(config.has_non_psi_checks());
+        assert!(!Config::default().has_non_psi_checks());
+        assert_eq!(config.psi_condition, PressureCondition::some_avg10(40.5));
+        assert_eq!(config.near_terminal, Threshold::Percent(20.0));
+        assert_eq!(config.max_sleep_ms, 2000);
+        assert!(config.kill_pgroup);
+        assert_eq!(config.memory_source, MemorySource::Meminfo);
+        assert_eq!(
+            config.kill_ram,
+            Some(Threshold::MinOf(5.0, 2 * 1024 * 1024))
+        );
+        assert_eq!(config.kill_swap, Some(Threshold::Percent(5.0)));
+        assert_eq!(config.combine, Combine::All);
+        assert_eq!(config.victim_policy, PolicyKind::AnonSwap);
+        let prefer: Vec<_> = config
+            .tiers
+            .prefer
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(prefer, ["chrom*", "cmdline:*-language-server*"]);
+        assert_eq!(
+            config.psi_trigger,
+            Some(TriggerSpec {
+                kind: PressureKind::Full,
+                stall_us: 150000,
+                window_us: 1000000
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_bad_values() {
+        assert!(Config::default().apply_str("not_a_key = 1").is_err());
+        assert!(Config::default().apply_str("cutoff_psi = lots").is_err());
+        assert!(Config::default().apply_str("cutoff_psi").is_err());
+        assert!(Config::default()
+            .apply_str("memory_source = vmstat")
+            .is_err());
+    }
+
+    #[test]
+    fn parses_cgroup_sections() {
+        let mut config = Config::default();
+        config
+            .apply_str(
+                "cutoff_psi = 30\n\
+                 [cgroup system.slice/docker-*.scope]\n\
+                 psi_condition = full avg10 > 20\n\
+                 kill_cgroup = yes\n\
+                 [cgroup /build.slice/]\n\
+                 max_usage_percent = 95\n\
+                 cooldown_ms = 30000\n\
+                 [cgroup ci.slice]\n\
+                 max_usage = min(90%, 6G)\n",
+            )
+            .unwrap();
+        config.validate().unwrap();
+
+        assert_eq!(config.psi_condition, PressureCondition::some_avg10(30.0));
+        assert_eq!(config.cgroups.len(), 3);
+        assert_eq!(config.cgroups[0].pattern, "system.slice/docker-*.scope");
+        assert_eq!(
+            config.cgroups[0].psi_condition,
+            Some("full avg10 > 20".parse().unwrap())
+        );
+        assert_eq!(config.cgroups[1].pattern, "build.slice");
+        assert_eq!(config.cgroups[1].max_usage, Some(Threshold::Percent(95.0)));
+        assert_eq!(
+            config.cgroups[2].max_usage,
+            Some(Threshold::MinOf(90.0, 6 * 1024 * 1024))
+        );
+        assert_eq!(config.cgroups[0].cooldown_ms, 10_000);
+        assert_eq!(config.cgroups[1].cooldown_ms, 30_000);
+        assert!(config.cgroups[0].kill_cgroup);
+        assert!(!config.cgroups[1].kill_cgroup);
+
+        // Global keys can't be set inside of a cgroup section
+        assert!(Config::default()
+            .apply_str("[cgroup user.slice]\ncutoff_psi = 10")
+            .is_err());
+    }
+
+    #[test]
+    fn validates_ranges() {
+        let mut config = Config::default();
+        config
+            .apply_str("min_sleep_ms = 500\nmax_sleep_ms = 100")
+            .unwrap();
+        assert!(config.validate().is_err());
+
+        assert!(Config::default()
+            .apply_str("ram_terminal_percent = 120")
+            .is_err());
+    }
+}
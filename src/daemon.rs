@@ -4,6 +4,19 @@ use daemonize::Daemonize;
 
 use crate::{error::Result, utils};
 
+/// Returns where the daemon's stdout, stderr and PID file go
+pub fn output_paths() -> (&'static str, &'static str, &'static str) {
+    if utils::running_as_sudo() {
+        (
+            "/var/log/bustd.out",
+            "/var/log/bustd.err",
+            "/var/run/bustd.pid",
+        )
+    } else {
+        ("/tmp/bustd.out", "/tmp/bustd.err", "/tmp/bustd.pid")
+    }
+}
+
 pub fn daemonize() -> Result<()> {
     let running_as_sudo = utils::running_as_sudo();
 
@@ -19,15 +32,7 @@ pub fn daemonize() -> Result<()> {
         .write(true)
         .to_owned();
 
-    let (stdout_path, stderr_path, pidfile_path) = if running_as_sudo {
-        (
-            "/var/log/bustd.out",
-            "/var/log/bustd.err",
-            "/var/run/bustd.pid",
-        )
-    } else {
-        ("/tmp/bustd.out", "/tmp/bustd.err", "/tmp/bustd.pid")
-    };
+    let (stdout_path, stderr_path, pidfile_path) = output_paths();
 
     let stdout = open_opts.open(stdout_path)?;
     let stderr = open_opts.open(stderr_path)?;
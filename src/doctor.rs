@@ -0,0 +1,317 @@
+use std::ffi::CString;
+use std::fmt;
+use std::path::Path;
+
+use libc::{EPERM, W_OK};
+
+use crate::capabilities::Capabilities;
+use crate::cli::CommandLineArgs;
+use crate::config::Config;
+use crate::daemon;
+use crate::errno::errno;
+use crate::error::Result;
+use crate::memory::pressure::{PressureKind, PressureSnapshot, MEMORY_PRESSURE};
+use crate::memory::{lock_memory_pages, MemoryInfo, MemorySource};
+use crate::uname::Uname;
+use crate::utils;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Verdict {
+    Pass,
+    /// `bustd` will run, but not as well as it could
+    Warn,
+    /// `bustd` won't run, or won't be able to protect the host
+    Fail,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Verdict::Pass => write!(f, "pass"),
+            Verdict::Warn => write!(f, "warn"),
+            Verdict::Fail => write!(f, "FAIL"),
+        }
+    }
+}
+
+struct Check {
+    name: &'static str,
+    verdict: Verdict,
+    detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, verdict: Verdict, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            verdict,
+            detail: detail.into(),
+        }
+    }
+}
+
+fn check_config(args: &CommandLineArgs) -> Check {
+    match Config::load(args) {
+        Ok(_) => Check::new("config", Verdict::Pass, "the configuration is valid"),
+        Err(err) => Check::new(
+            "config",
+            Verdict::Fail,
+            format!("the configuration can't be loaded: {}", err),
+        ),
+    }
+}
+
+fn check_mlockall(capabilities: &Capabilities) -> Check {
+    match lock_memory_pages(capabilities.mcl_onfault) {
+        Ok(()) => {
+            // Safety: munlockall is safe
+            unsafe { libc::munlockall() };
+            Check::new("mlockall", Verdict::Pass, "memory pages can be locked")
+        }
+        Err(err) => Check::new(
+            "mlockall",
+            Verdict::Warn,
+            format!(
+                "memory pages can't be locked ({}), so bustd may be swapped out under pressure",
+                err
+            ),
+        ),
+    }
+}
+
+fn check_signals() -> Check {
+    // Signal 0 only checks whether init could be signalled, and init belongs to root
+    // Safety: kill is safe
+    let res = unsafe { libc::kill(1, 0) };
+
+    if res == 0 {
+        Check::new(
+            "signals",
+            Verdict::Pass,
+            "processes of other users can be killed",
+        )
+    } else if errno() == EPERM {
+        Check::new(
+            "signals",
+            Verdict::Fail,
+            "only processes of the current user can be killed: run bustd as root",
+        )
+    } else {
+        Check::new(
+            "signals",
+            Verdict::Warn,
+            format!("could not check: {}", std::io::Error::last_os_error()),
+        )
+    }
+}
+
+fn check_psi(buf: &mut [u8]) -> Check {
+    match PressureSnapshot::from_file(MEMORY_PRESSURE, buf) {
+        Ok(snapshot) => Check::new(
+            "PSI",
+            Verdict::Pass,
+            format!(
+                "{} parses (some avg10 = {:.2})",
+                MEMORY_PRESSURE,
+                snapshot.line(PressureKind::Some).avg10
+            ),
+        ),
+        Err(err) => Check::new(
+            "PSI",
+            Verdict::Warn,
+            format!(
+                "{} is unavailable ({}): only RAM and swap thresholds will be used",
+                MEMORY_PRESSURE, err
+            ),
+        ),
+    }
+}
+
+fn check_kernel() -> Check {
+    let version = Uname::new().and_then(|uname| uname.parse_version());
+
+    match version {
+        Ok(version) if version.supports_psi() => {
+            Check::new("kernel", Verdict::Pass, format!("Linux {}", version))
+        }
+        Ok(version) => Check::new(
+            "kernel",
+            Verdict::Warn,
+            format!("Linux {} predates PSI (4.20)", version),
+        ),
+        Err(err) => Check::new(
+            "kernel",
+            Verdict::Warn,
+            format!("could not parse the kernel version: {}", err),
+        ),
+    }
+}
+
+fn check_swap() -> Check {
+    match MemoryInfo::new(MemorySource::Sysinfo, &mut []) {
+        Ok(memory_info) if memory_info.total_swap_kib > 0 => Check::new(
+            "swap",
+            Verdict::Pass,
+            format!("{} MiB of swap", memory_info.total_swap_kib / 1024),
+        ),
+        Ok(_) => Check::new(
+            "swap",
+            Verdict::Warn,
+            "no swap: memory can run out too quickly for pressure to build up",
+        ),
+        Err(err) => Check::new("swap", Verdict::Warn, format!("could not check: {}", err)),
+    }
+}
+
+/// Returns true if `path` can be written to or, if it doesn't exist yet, created
+fn is_writable(path: &str) -> bool {
+    let path = Path::new(path);
+    let target = if path.exists() {
+        path
+    } else {
+        path.parent().unwrap_or(path)
+    };
+
+    let target = match target.to_str().and_then(|t| CString::new(t).ok()) {
+        Some(target) => target,
+        None => return false,
+    };
+
+    // Safety: `target` is a valid NUL-terminated string
+    unsafe { libc::access(target.as_ptr(), W_OK) == 0 }
+}
+
+fn check_paths() -> Check {
+    let (stdout_path, stderr_path, pidfile_path) = daemon::output_paths();
+
+    let not_writable: Vec<_> = [stdout_path, stderr_path, pidfile_path]
+        .iter()
+        .filter(|path| !is_writable(path))
+        .copied()
+        .collect();
+
+    if not_writable.is_empty() {
+        Check::new(
+            "paths",
+            Verdict::Pass,
+            format!(
+                "{}, {} and {} are writable",
+                stdout_path, stderr_path, pidfile_path
+            ),
+        )
+    } else {
+        Check::new(
+            "paths",
+            Verdict::Fail,
+            format!(
+                "{} can't be written to, so bustd can't daemonize (unless run with -n)",
+                not_writable.join(", ")
+            ),
+        )
+    }
+}
+
+fn check_glob_ignore() -> Check {
+    let detail = if cfg!(feature = "glob-ignore") {
+        "compiled in: unkillable patterns can be used"
+    } else {
+        "not compiled in: unkillable patterns can't be used"
+    };
+
+    Check::new("glob-ignore", Verdict::Pass, detail)
+}
+
+fn check_regex_rules() -> Check {
+    let detail = if cfg!(feature = "regex-rules") {
+        "compiled in: rules can use regexes"
+    } else {
+        "not compiled in: rules can only use globs"
+    };
+
+    Check::new("regex-rules", Verdict::Pass, detail)
+}
+
+/// Returns true if none of the checks failed. Warnings don't count
+fn passed(checks: &[Check]) -> bool {
+    checks.iter().all(|check| check.verdict != Verdict::Fail)
+}
+
+/// Checks whether `bustd` can do its job on this host and prints the results.
+/// Runs before the configuration is loaded, so that a broken one is reported as a
+/// failed check instead of keeping the rest from being checked.
+///
+/// Returns false if any of the checks failed.
+pub fn run(args: &CommandLineArgs, buf: &mut [u8]) -> Result<bool> {
+    let capabilities = Capabilities::probe(Uname::new()?.parse_version().ok(), buf);
+
+    let checks = [
+        check_config(args),
+        check_mlockall(&capabilities),
+        check_signals(),
+        check_psi(buf),
+        check_kernel(),
+        check_swap(),
+        check_paths(),
+        check_glob_ignore(),
+        check_regex_rules(),
+    ];
+
+    for check in &checks {
+        println!("[{}] {:<12} {}", check.verdict, check.name, check.detail);
+    }
+
+    let user = utils::get_username().unwrap_or_else(|| "unknown".into());
+    println!("\nChecked as user {}.", user);
+
+    Ok(passed(&checks))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use argh::FromArgs;
+
+    use super::{check_config, is_writable, passed, Check, Verdict};
+    use crate::cli::CommandLineArgs;
+
+    #[test]
+    fn reports_broken_configs() {
+        let dir = std::env::temp_dir().join(format!("bustd-doctor-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("bustd.conf");
+        let path_str = path.to_str().unwrap();
+        let args =
+            CommandLineArgs::from_args(&["bustd"], &["--config", path_str, "doctor"]).unwrap();
+
+        fs::write(&path, "cutoff_psi = 30\n").unwrap();
+        assert_eq!(check_config(&args).verdict, Verdict::Pass);
+
+        fs::write(&path, "cutoff_psi = lots\n").unwrap();
+        let check = check_config(&args);
+        assert_eq!(check.verdict, Verdict::Fail);
+        assert!(check.detail.contains("cutoff_psi"));
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(check_config(&args).verdict, Verdict::Fail);
+    }
+
+    #[test]
+    fn only_failures_fail() {
+        let pass = || Check::new("pass", Verdict::Pass, "");
+        let warn = || Check::new("warn", Verdict::Warn, "");
+        let fail = || Check::new("fail", Verdict::Fail, "");
+
+        assert!(passed(&[pass(), warn()]));
+        assert!(!passed(&[pass(), fail(), warn()]));
+    }
+
+    #[test]
+    fn writable_paths() {
+        let dir = std::env::temp_dir();
+        assert!(is_writable(dir.to_str().unwrap()));
+        // Doesn't exist yet, but can be created
+        assert!(is_writable(dir.join("bustd-doctor.pid").to_str().unwrap()));
+        assert!(!is_writable("/proc/self/bustd.pid"));
+    }
+}
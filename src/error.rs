@@ -1,4 +1,4 @@
-use std::{any::Any, str::Utf8Error};
+use std::{any::Any, fmt, str::Utf8Error};
 
 use daemonize::DaemonizeError;
 
@@ -10,6 +10,8 @@ pub enum Error {
     InvalidPidSupplied,
     ProcessGroupNotFound,
     InvalidSignal,
+    /// The victim with this PID was sent a SIGKILL but still didn't exit
+    VictimSurvived(u32),
     Io {
         reason: String,
     },
@@ -20,6 +22,9 @@ pub enum Error {
         error: Utf8Error,
     },
     NoPermission,
+    InvalidConfig {
+        reason: String,
+    },
 
     // mlockall-specific errors
     CouldNotLockMemory,
@@ -41,7 +46,12 @@ pub enum Error {
     // Errors that are likely impossible to happen
     InvalidLinuxVersion,
     MalformedStatm,
+    MalformedStat,
     MalformedPressureFile,
+    PressureTriggerGone,
+    MalformedMeminfo,
+    MalformedCgroupFile,
+    LineTooLong,
     StringFromBytes,
     ParseInt,
     ParseFloat,
@@ -51,6 +61,96 @@ pub enum Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+    /// Returns true if retrying may succeed, e.g. because a victim exited before we got
+    /// to kill it or because a `/proc` file was read while being updated. Fatal errors
+    /// come from the way `bustd` was set up and won't go away by themselves
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::ProcessNotFound(_)
+            | Error::InvalidPidSupplied
+            | Error::ProcessGroupNotFound
+            // E.g. stuck in uninterruptible sleep, which it may come out of
+            | Error::VictimSurvived(_)
+            | Error::Io { .. }
+            | Error::Unicode { .. }
+            | Error::UnknownGetpguid
+            | Error::MalformedStatm
+            | Error::MalformedStat
+            | Error::MalformedPressureFile
+            | Error::MalformedMeminfo
+            | Error::MalformedCgroupFile
+            | Error::LineTooLong
+            | Error::StringFromBytes
+            | Error::ParseInt
+            | Error::ParseFloat
+            | Error::SysInfoFailed => true,
+
+            Error::UnameFailed
+            | Error::InvalidSignal
+            // Victims are signalled through a pidfd when possible, so this likely
+            // means bustd lacks the privileges to kill anything
+            | Error::NoPermission
+            | Error::UnknownKill
+            | Error::Daemonize { .. }
+            | Error::InvalidConfig { .. }
+            | Error::CouldNotLockMemory
+            | Error::TooMuchMemoryToLock
+            | Error::InvalidFlags
+            | Error::UnknownMlockall
+            | Error::Thread { .. }
+            | Error::InvalidLinuxVersion
+            | Error::PressureTriggerGone
+            | Error::SysConfFailed => false,
+
+            #[cfg(feature = "glob-ignore")]
+            Error::GlobPattern { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::UnameFailed => write!(f, "uname failed"),
+            Error::ProcessNotFound(origin) => write!(f, "process not found ({})", origin),
+            Error::InvalidPidSupplied => write!(f, "invalid PID supplied"),
+            Error::ProcessGroupNotFound => write!(f, "process group not found"),
+            Error::InvalidSignal => write!(f, "invalid signal"),
+            Error::VictimSurvived(pid) => {
+                write!(f, "PID {} did not exit after being sent a SIGKILL", pid)
+            }
+            Error::Io { reason } => write!(f, "I/O error: {}", reason),
+            Error::Daemonize { error } => write!(f, "failed to daemonize: {}", error),
+            Error::Unicode { error } => write!(f, "invalid UTF-8: {}", error),
+            Error::NoPermission => write!(f, "operation not permitted"),
+            Error::InvalidConfig { reason } => write!(f, "invalid configuration: {}", reason),
+            Error::CouldNotLockMemory => write!(f, "could not lock memory"),
+            Error::TooMuchMemoryToLock => write!(f, "too much memory to lock"),
+            Error::InvalidFlags => write!(f, "invalid flags"),
+            Error::UnknownMlockall => write!(f, "unknown mlockall error"),
+            Error::UnknownKill => write!(f, "unknown kill error"),
+            Error::UnknownGetpguid => write!(f, "unknown getpgid error"),
+            Error::Thread { error } => write!(f, "thread panicked: {:?}", error),
+            #[cfg(feature = "glob-ignore")]
+            Error::GlobPattern { error } => write!(f, "invalid glob pattern: {}", error),
+            Error::InvalidLinuxVersion => write!(f, "invalid Linux version"),
+            Error::MalformedStatm => write!(f, "malformed statm file"),
+            Error::MalformedStat => write!(f, "malformed stat file"),
+            Error::MalformedPressureFile => write!(f, "malformed pressure file"),
+            Error::PressureTriggerGone => write!(f, "the PSI trigger is no longer valid"),
+            Error::MalformedMeminfo => write!(f, "malformed /proc/meminfo"),
+            Error::MalformedCgroupFile => write!(f, "malformed cgroup file"),
+            Error::LineTooLong => write!(f, "line too long for the supplied buffer"),
+            Error::StringFromBytes => write!(f, "could not build string from bytes"),
+            Error::ParseInt => write!(f, "could not parse integer"),
+            Error::ParseFloat => write!(f, "could not parse float"),
+            Error::SysConfFailed => write!(f, "sysconf failed"),
+            Error::SysInfoFailed => write!(f, "sysinfo failed"),
+        }
+    }
+}
+
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Self {
         Self::Io {
@@ -95,3 +195,19 @@ impl From<glob::PatternError> for Error {
         Self::GlobPattern { error }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+
+    #[test]
+    fn transient_errors() {
+        assert!(Error::ProcessNotFound("kill").is_transient());
+        assert!(Error::VictimSurvived(1234).is_transient());
+        assert!(Error::from(std::io::Error::from(std::io::ErrorKind::NotFound)).is_transient());
+
+        assert!(!Error::NoPermission.is_transient());
+        assert!(!Error::UnknownKill.is_transient());
+        assert!(!Error::InvalidSignal.is_transient());
+    }
+}
@@ -0,0 +1,207 @@
+use crate::cgroup;
+use crate::config::Config;
+use crate::error::Result;
+use crate::kill::{self, Candidate, Exclusion};
+use crate::policy::PolicyKind;
+use crate::process::Process;
+use crate::tier::Tier;
+use crate::utils::json_string;
+
+/// A process as seen by `bustd explain`
+struct Entry {
+    pid: u32,
+    comm: String,
+    cmdline: String,
+    /// `None` if the process couldn't be read at all
+    oom_score: Option<i16>,
+    oom_score_adj: Option<i16>,
+    vm_rss_kib: Option<i64>,
+    /// Given by the victim policy
+    score: Option<i64>,
+    tier: Option<Tier>,
+    exclusion: Option<Exclusion>,
+}
+
+impl Entry {
+    fn new(process: &Process, buf: &mut [u8]) -> Self {
+        Self {
+            pid: process.pid,
+            comm: process
+                .comm(buf)
+                .map(|comm| comm.trim().to_owned())
+                .unwrap_or_default(),
+            cmdline: process.cmdline().unwrap_or_default(),
+            oom_score: Some(process.oom_score),
+            oom_score_adj: None,
+            vm_rss_kib: None,
+            score: None,
+            tier: None,
+            exclusion: None,
+        }
+    }
+
+    fn candidate(candidate: &Candidate, score: Option<i64>, buf: &mut [u8]) -> Self {
+        Self {
+            oom_score_adj: Some(candidate.oom_score_adj),
+            vm_rss_kib: Some(candidate.vm_rss_kib),
+            score,
+            tier: Some(candidate.tier),
+            ..Self::new(&candidate.process, buf)
+        }
+    }
+
+    fn excluded(process: &Process, exclusion: Exclusion, buf: &mut [u8]) -> Self {
+        Self {
+            oom_score_adj: process.oom_score_adj(buf).ok(),
+            vm_rss_kib: process.vm_rss_kib(buf).ok(),
+            exclusion: Some(exclusion),
+            ..Self::new(process, buf)
+        }
+    }
+
+    /// A process whose `/proc` entry couldn't be read, such that only its PID is known
+    fn unreadable(pid: u32) -> Self {
+        Self {
+            pid,
+            comm: String::new(),
+            cmdline: String::new(),
+            oom_score: None,
+            oom_score_adj: None,
+            vm_rss_kib: None,
+            score: None,
+            tier: None,
+            exclusion: Some(Exclusion::ReadFailure),
+        }
+    }
+
+    fn to_json(&self, rank: Option<usize>) -> String {
+        let number = |n: Option<i64>| n.map_or("null".to_owned(), |n| n.to_string());
+
+        let mut json = String::from("{");
+        if let Some(rank) = rank {
+            json.push_str(&format!("\"rank\":{},", rank));
+        }
+        json.push_str(&format!(
+            "\"pid\":{},\"comm\":{},\"cmdline\":{},\"oom_score\":{},\"oom_score_adj\":{},\"vm_rss_kib\":{},\"score\":{},\"tier\":{}",
+            self.pid,
+            json_string(&self.comm),
+            json_string(&self.cmdline),
+            number(self.oom_score.map(i64::from)),
+            number(self.oom_score_adj.map(i64::from)),
+            number(self.vm_rss_kib),
+            number(self.score),
+            self.tier
+                .map_or("null".to_owned(), |tier| json_string(tier.as_str())),
+        ));
+        if let Some(exclusion) = self.exclusion {
+            json.push_str(&format!(",\"reason\":{}", json_string(exclusion.as_str())));
+        }
+        json.push('}');
+
+        json
+    }
+}
+
+fn print_table(policy: PolicyKind, candidates: &[Entry], excluded: &[Entry]) {
+    println!("Ranked by {}:", policy.as_str());
+    println!(
+        "{:>4} {:>7} {:<10} {:>12} {:>9} {:>6} {:>10}  {:<16} CMDLINE",
+        "RANK", "PID", "TIER", "SCORE", "OOM_SCORE", "ADJ", "RSS (KiB)", "COMM"
+    );
+    for (idx, entry) in candidates.iter().enumerate() {
+        println!(
+            "{:>4} {:>7} {:<10} {:>12} {:>9} {:>6} {:>10}  {:<16} {}",
+            idx + 1,
+            entry.pid,
+            entry.tier.map_or("", |tier| tier.as_str()),
+            // Not scored, see `kill::rank`
+            entry
+                .score
+                .map_or("-".to_owned(), |score| score.to_string()),
+            entry.oom_score.unwrap_or_default(),
+            entry.oom_score_adj.unwrap_or_default(),
+            entry.vm_rss_kib.unwrap_or_default(),
+            entry.comm,
+            // Keep each process on its own line
+            entry.cmdline.replace(char::is_control, " ")
+        );
+    }
+
+    println!("\nExcluded:");
+    println!("{:>7}  {:<16} REASON", "PID", "COMM");
+    for entry in excluded {
+        let reason = entry.exclusion.map_or("", |exclusion| exclusion.as_str());
+        println!("{:>7}  {:<16} {}", entry.pid, entry.comm, reason);
+    }
+}
+
+fn print_json(policy: PolicyKind, candidates: &[Entry], excluded: &[Entry]) {
+    let candidates: Vec<_> = candidates
+        .iter()
+        .enumerate()
+        .map(|(idx, entry)| entry.to_json(Some(idx + 1)))
+        .collect();
+    let excluded: Vec<_> = excluded.iter().map(|entry| entry.to_json(None)).collect();
+
+    println!(
+        "{{\"policy\":{},\"candidates\":[{}],\"excluded\":[{}]}}",
+        json_string(policy.as_str()),
+        candidates.join(","),
+        excluded.join(",")
+    );
+}
+
+/// Prints every process `bustd` could kill right now, ranked in the order
+/// it would choose them, along with the processes it would never kill and why
+pub fn run(config: &Config, json: bool) -> Result<()> {
+    let mut proc_buf = [0_u8; 50];
+    let mut buf = [0_u8; 512];
+
+    // Victims are only searched inside of the container we're running in, if any.
+    // Just like `Monitor::new`, only the unified hierarchy has memory limits to go by
+    let scope = if config.container_aware && cgroup::is_v2_mounted() {
+        cgroup::limited_own_cgroup(&mut buf)?
+    } else {
+        None
+    };
+
+    let mut candidates = Vec::new();
+    let mut excluded = Vec::new();
+
+    for pid in kill::pids()? {
+        let process = match Process::from_pid(pid, &mut proc_buf) {
+            Ok(process) => process,
+            // It likely exited in the meantime, but it's listed in case it didn't
+            Err(_) => {
+                excluded.push(Entry::unreadable(pid));
+                continue;
+            }
+        };
+
+        match kill::assess(process, &mut buf, config, scope.as_deref()) {
+            Ok(candidate) => candidates.push(candidate),
+            Err((process, exclusion)) => {
+                excluded.push(Entry::excluded(&process, exclusion, &mut buf))
+            }
+        }
+    }
+
+    // The same order `choose_victim` goes by
+    let candidates: Vec<_> = kill::rank(
+        candidates,
+        &mut buf,
+        config,
+        &*config.victim_policy.policy(),
+    )
+    .iter()
+    .map(|(candidate, score)| Entry::candidate(candidate, *score, &mut buf))
+    .collect();
+
+    if json {
+        print_json(config.victim_policy, &candidates, &excluded);
+    } else {
+        print_table(config.victim_policy, &candidates, &excluded);
+    }
+
+    Ok(())
+}
@@ -1,28 +1,72 @@
+use std::fmt;
 use std::fs;
+use std::os::unix::io::RawFd;
 use std::time::Duration;
-use std::{ffi::OsStr, time::Instant};
+use std::{ffi::OsStr, ptr, time::Instant};
 
 use libc::kill;
 use libc::{EINVAL, EPERM, ESRCH, SIGKILL, SIGTERM};
 
+use crate::capabilities::{Capabilities, SYS_PROCESS_MRELEASE};
+use crate::config::Config;
 use crate::errno::errno;
 use crate::error::{Error, Result};
+use crate::policy::{PolicyKind, VictimPolicy};
 use crate::process::Process;
-use crate::{cli, utils};
+#[cfg(feature = "glob-ignore")]
+use crate::rule::Field;
+use crate::rule::Fields;
+use crate::tier::Tier;
+use crate::utils;
 
-pub fn choose_victim(
-    proc_buf: &mut [u8],
-    buf: &mut [u8],
-    args: &cli::CommandLineArgs,
-) -> Result<Process> {
-    let now = Instant::now();
+/// Why a process can't be chosen as a victim
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exclusion {
+    Init,
+    /// `bustd` itself
+    Bustd,
+    KernelThread,
+    /// Its `oom_score_adj` is -1000, which the kernel's OOM killer respects as well
+    OomScoreAdjMin,
+    /// Its name matches an unkillable pattern
+    #[cfg(feature = "glob-ignore")]
+    Unkillable,
+    /// It matches a `protect` rule
+    Protected,
+    /// It lives outside of the cgroup victims are searched in
+    OutsideCgroup,
+    /// Some of its `/proc` files couldn't be read, likely because it exited in the meantime
+    ReadFailure,
+}
 
-    // `args` is currently only used when checking for unkillable patterns
-    #[cfg(not(feature = "glob-ignore"))]
-    let _ = args;
+impl Exclusion {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Exclusion::Init => "init",
+            Exclusion::Bustd => "bustd itself",
+            Exclusion::KernelThread => "kernel thread",
+            Exclusion::OomScoreAdjMin => "oom_score_adj is -1000",
+            #[cfg(feature = "glob-ignore")]
+            Exclusion::Unkillable => "matches an unkillable pattern",
+            Exclusion::Protected => "protected",
+            Exclusion::OutsideCgroup => "outside of the monitored cgroup",
+            Exclusion::ReadFailure => "could not be read",
+        }
+    }
+}
 
-    let mut processes = fs::read_dir("/proc/")?
-        .into_iter()
+/// A process which may be chosen as a victim
+#[derive(Debug)]
+pub struct Candidate {
+    pub process: Process,
+    pub vm_rss_kib: i64,
+    pub oom_score_adj: i16,
+    pub tier: Tier,
+}
+
+/// Returns the PIDs of every process currently running
+pub fn pids() -> Result<impl Iterator<Item = u32>> {
+    let pids = fs::read_dir("/proc/")?
         .filter_map(|e| e.ok())
         .filter_map(|entry| {
             entry
@@ -35,109 +79,457 @@ pub fn choose_victim(
                 .parse::<u32>()
                 .ok()
         })
-        .filter(|pid| *pid > 1)
-        .filter_map(|pid| Process::from_pid(pid, proc_buf).ok());
+        .filter(|pid| *pid > 0);
 
-    let first_process = processes.next();
-    if first_process.is_none() {
-        // Likely an impossible scenario but we found no process to kill!
-        return Err(Error::ProcessNotFound("choose_victim"));
-    }
+    Ok(pids)
+}
+
+/// Checks whether `process` may be chosen as a victim. If `cgroup` is given,
+/// only processes inside of it (or of its descendants) may be.
+pub fn assess(
+    process: Process,
+    buf: &mut [u8],
+    config: &Config,
+    cgroup: Option<&str>,
+) -> std::result::Result<Candidate, (Process, Exclusion)> {
+    assess_with(process, buf, config, cgroup, false)
+}
 
-    let mut victim = first_process.unwrap();
-    // TODO: find another victim if victim.vm_rss_kib() fails here
-    let mut victim_vm_rss_kib = victim.vm_rss_kib(buf)?;
+/// Like `assess`, but only returns candidates and logs unkillable processes
+fn assess_logged(
+    process: Process,
+    buf: &mut [u8],
+    config: &Config,
+    cgroup: Option<&str>,
+) -> Option<Candidate> {
+    // TODO: warn about read failures
+    assess_with(process, buf, config, cgroup, true).ok()
+}
 
-    for process in processes {
-        if victim.oom_score > process.oom_score {
-            // Our current victim is less innocent than the process being analysed
-            continue;
+fn assess_with(
+    process: Process,
+    buf: &mut [u8],
+    config: &Config,
+    cgroup: Option<&str>,
+    log_unkillable: bool,
+) -> std::result::Result<Candidate, (Process, Exclusion)> {
+    if process.pid == 1 {
+        return Err((process, Exclusion::Init));
+    }
+    if process.pid == std::process::id() {
+        return Err((process, Exclusion::Bustd));
+    }
+
+    if let Some(cgroup) = cgroup {
+        if !matches!(process.is_in_cgroup(buf, cgroup), Ok(true)) {
+            return Err((process, Exclusion::OutsideCgroup));
         }
+    }
 
-        #[cfg(feature = "glob-ignore")]
-        {
-            if let Some(patterns) = &args.ignored {
-                if matches!(process.is_unkillable(&mut buf, patterns), Ok(true)) {
-                    continue;
+    // The cheap checks come first, so that rules aren't matched against kernel threads
+    let vm_rss_kib = match process.vm_rss_kib(buf) {
+        Ok(vm_rss_kib) => vm_rss_kib,
+        Err(_) => return Err((process, Exclusion::ReadFailure)),
+    };
+    if vm_rss_kib == 0 {
+        return Err((process, Exclusion::KernelThread));
+    }
+
+    let oom_score_adj = match process.oom_score_adj(buf) {
+        Ok(oom_score_adj) => oom_score_adj,
+        Err(_) => return Err((process, Exclusion::ReadFailure)),
+    };
+    if oom_score_adj == -1000 {
+        // Follow the behaviour of the standard OOM killer: don't kill processes with oom_score_adj equals to -1000
+        return Err((process, Exclusion::OomScoreAdjMin));
+    }
+
+    let tier = match classify(&process, buf, config, log_unkillable) {
+        Ok(tier) => tier,
+        Err(exclusion) => return Err((process, exclusion)),
+    };
+
+    Ok(Candidate {
+        process,
+        vm_rss_kib,
+        oom_score_adj,
+        tier,
+    })
+}
+
+/// Sorts `candidates` in the order victims are chosen in, along with their scores.
+///
+/// Candidates from earlier tiers always come first. If the victim policy is expensive, only
+/// the `config.smaps_candidates` candidates with the largest RSS (from the first tiers) are
+/// scored, and the others come last within their tier. So do candidates which couldn't be
+/// scored.
+pub fn rank(
+    mut candidates: Vec<Candidate>,
+    buf: &mut [u8],
+    config: &Config,
+    policy: &dyn VictimPolicy,
+) -> Vec<(Candidate, Option<i64>)> {
+    let shortlist = if policy.is_expensive() {
+        candidates
+            .sort_by_key(|candidate| std::cmp::Reverse((candidate.tier, candidate.vm_rss_kib)));
+        config.smaps_candidates
+    } else {
+        candidates.len()
+    };
+
+    let mut ranked: Vec<_> = candidates
+        .into_iter()
+        .enumerate()
+        .map(|(idx, candidate)| {
+            let score = if idx < shortlist {
+                policy.score(&candidate, buf).ok()
+            } else {
+                None
+            };
+            (candidate, score)
+        })
+        .collect();
+
+    ranked.sort_by(|(a, a_score), (b, b_score)| {
+        (b.tier, b_score.is_some(), b_score, b.vm_rss_kib).cmp(&(
+            a.tier,
+            a_score.is_some(),
+            a_score,
+            a.vm_rss_kib,
+        ))
+    });
+
+    ranked
+}
+
+/// Matches `process` against the unkillable patterns and the tier rules,
+/// reading each of its fields at most once for all of them
+#[cfg_attr(not(feature = "glob-ignore"), allow(unused_variables))]
+fn classify(
+    process: &Process,
+    buf: &mut [u8],
+    config: &Config,
+    log_unkillable: bool,
+) -> std::result::Result<Tier, Exclusion> {
+    let mut fields = Fields::new(process);
+
+    #[cfg(feature = "glob-ignore")]
+    {
+        if let Some(patterns) = &config.ignored {
+            if let Ok(Some(comm)) = fields.get(Field::Comm, buf) {
+                if patterns.iter().any(|pattern| pattern.matches(comm)) {
+                    if log_unkillable {
+                        println!(
+                            "Skipping \"{}\" since it matches an unkillable pattern",
+                            comm
+                        );
+                    }
+                    return Err(Exclusion::Unkillable);
                 }
             }
         }
+    }
 
-        let cur_vm_rss_kib = process.vm_rss_kib(buf)?;
-        if cur_vm_rss_kib == 0 {
-            // Current process is a kernel thread
-            continue;
-        }
+    if config.tiers.is_empty() {
+        return Ok(Tier::Normal);
+    }
+    match config.tiers.tier_of(&mut fields, buf) {
+        Ok(Tier::Protected) => Err(Exclusion::Protected),
+        Ok(tier) => Ok(tier),
+        Err(_) => Err(Exclusion::ReadFailure),
+    }
+}
 
-        if process.oom_score == victim.oom_score && cur_vm_rss_kib <= victim_vm_rss_kib {
-            continue;
-        }
+/// Chooses the process to be killed, going through `config.tiers` in order and by
+/// `config.victim_policy` within them. If `cgroup` is given, only processes inside
+/// of it (or of its descendants) are considered.
+///
+/// When supported, a pidfd to the victim is opened right away, so that it can't be
+/// mistaken for a process reusing its PID if it exits before being killed
+pub fn choose_victim(
+    proc_buf: &mut [u8],
+    buf: &mut [u8],
+    config: &Config,
+    policy: &dyn VictimPolicy,
+    capabilities: &Capabilities,
+    cgroup: Option<&str>,
+) -> Result<Process> {
+    let processes = pids()?.filter_map(|pid| Process::from_pid(pid, proc_buf).ok());
 
-        let cur_oom_score_adj = match process.oom_score_adj(buf) {
-            Ok(oom_score_adj) => oom_score_adj,
-            // TODO: warn that this error happened
-            Err(_) => continue,
-        };
+    choose_victim_among(processes, buf, config, policy, capabilities, cgroup)
+}
 
-        if cur_oom_score_adj == -1000 {
-            // Follow the behaviour of the standard OOM killer: don't kill processes with oom_score_adj equals to -1000
-            continue;
-        }
+/// Chooses the process to be killed out of `processes`, just like `choose_victim`
+fn choose_victim_among(
+    processes: impl Iterator<Item = Process>,
+    buf: &mut [u8],
+    config: &Config,
+    policy: &dyn VictimPolicy,
+    capabilities: &Capabilities,
+    cgroup: Option<&str>,
+) -> Result<Process> {
+    let now = Instant::now();
+
+    let victim = if policy.is_expensive() {
+        let candidates = processes
+            .filter_map(|process| assess_logged(process, buf, config, cgroup))
+            .collect();
+
+        rank(candidates, buf, config, policy)
+            .into_iter()
+            .next()
+            .map(|(candidate, score)| {
+                // Memory is low, so rather kill the largest process of the first tier,
+                // going by the RSS its unscored candidates are ordered by, than nothing at all
+                let score = score.unwrap_or_else(|| {
+                    println!(
+                        "[LOG] Could not read the {} of any candidate in the {} tier. Going by RSS instead.",
+                        config.victim_policy.as_str(),
+                        candidate.tier.as_str()
+                    );
+                    candidate.vm_rss_kib
+                });
+                (candidate, score)
+            })
+    } else {
+        choose_victim_cheaply(processes, buf, config, policy, cgroup)
+    };
+
+    // Likely an impossible scenario (unless scoped to a cgroup) but we found no process to kill!
+    let (victim, score) = victim.ok_or(Error::ProcessNotFound("choose_victim"))?;
+    let (mut victim, tier) = (victim.process, victim.tier);
 
-        // eprintln!("[DBG] New victim with PID={}!", process.pid);
-        victim = process;
-        victim_vm_rss_kib = cur_vm_rss_kib;
+    if capabilities.pidfd_open && capabilities.pidfd_send_signal {
+        victim.pidfd = Some(PidFd::open(victim.pid)?);
     }
 
     println!("[LOG] Found victim in {} secs.", now.elapsed().as_secs());
-    println!(
+    print!(
         "[LOG] Victim => pid: {}, comm: {}, oom_score: {}",
         victim.pid,
         victim.comm(buf).unwrap_or("unknown").trim(),
         victim.oom_score
     );
+    if config.victim_policy != PolicyKind::OomScore {
+        print!(", {}: {}", config.victim_policy.as_str(), score);
+    }
+    if !config.tiers.is_empty() {
+        print!(", tier: {}", tier.as_str());
+    }
+    println!();
 
     Ok(victim)
 }
 
+/// Goes through `processes` without keeping more than the victim so far around,
+/// for policies which are cheap enough to score every candidate with
+fn choose_victim_cheaply(
+    processes: impl Iterator<Item = Process>,
+    buf: &mut [u8],
+    config: &Config,
+    policy: &dyn VictimPolicy,
+    cgroup: Option<&str>,
+) -> Option<(Candidate, i64)> {
+    // The victim so far, along with its score
+    let mut victim: Option<(Candidate, i64)> = None;
+
+    for process in processes {
+        if let (Some((victim, victim_score)), Some(score)) =
+            (&victim, policy.score_upfront(&process))
+        {
+            // A process from an earlier tier would beat our victim regardless of its score
+            if victim.tier >= config.tiers.first() && *victim_score > score {
+                // Our current victim is less innocent than the process being analysed
+                continue;
+            }
+        }
+
+        let candidate = match assess_logged(process, buf, config, cgroup) {
+            Some(candidate) => candidate,
+            None => continue,
+        };
+
+        let score = match policy.score(&candidate, buf) {
+            Ok(score) => score,
+            // It likely exited in the meantime
+            Err(_) => continue,
+        };
+
+        if let Some((victim, victim_score)) = &victim {
+            if (candidate.tier, score, candidate.vm_rss_kib)
+                <= (victim.tier, *victim_score, victim.vm_rss_kib)
+            {
+                continue;
+            }
+        }
+
+        // eprintln!("[DBG] New victim with PID={}!", candidate.process.pid);
+        victim = Some((candidate, score));
+    }
+
+    victim
+}
+
 pub fn kill_process(pid: i32, signal: i32) -> Result<()> {
     let res = unsafe { kill(pid, signal) };
 
     if res == -1 {
-        return Err(match errno() {
-            // An invalid signal was specified
-            EINVAL => Error::InvalidSignal,
-            // Calling process doesn't have permission to send signals to any
-            // of the target processes
-            EPERM => Error::NoPermission,
-            // The target process or process group does not exist.
-            ESRCH => Error::ProcessNotFound("kill"),
-            _ => Error::UnknownKill,
-        });
+        return Err(kill_error("kill"));
     }
 
     Ok(())
 }
 
-pub fn kill_process_group(process: Process) -> Result<()> {
-    let pid = process.pid;
+/// Maps the errno of a failed `kill` or `pidfd_send_signal`
+fn kill_error(origin: &'static str) -> Error {
+    match errno() {
+        // An invalid signal was specified
+        EINVAL => Error::InvalidSignal,
+        // Calling process doesn't have permission to send signals to any
+        // of the target processes
+        EPERM => Error::NoPermission,
+        // The target process or process group does not exist.
+        ESRCH => Error::ProcessNotFound(origin),
+        _ => Error::UnknownKill,
+    }
+}
 
-    let pgid = utils::get_process_group(pid as i32)?;
+/// A file descriptor referring to a process. Unlike its PID,
+/// it can't end up referring to another process once it exits
+#[derive(Debug)]
+pub struct PidFd {
+    fd: RawFd,
+}
 
-    // TODO: kill and wait
-    let _ = kill_process(-pgid, SIGTERM);
+impl PidFd {
+    pub fn open(pid: u32) -> Result<Self> {
+        // Safety: pidfd_open has no side effects other than returning a file descriptor
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+        if fd < 0 {
+            return Err(match errno() {
+                ESRCH => Error::ProcessNotFound("pidfd_open"),
+                _ => std::io::Error::last_os_error().into(),
+            });
+        }
 
-    Ok(())
+        Ok(Self { fd: fd as RawFd })
+    }
+
+    pub fn send_signal(&self, signal: i32) -> Result<()> {
+        // Safety: `self.fd` is a valid pidfd and a null siginfo is allowed
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_pidfd_send_signal,
+                self.fd,
+                signal,
+                ptr::null::<libc::siginfo_t>(),
+                0,
+            )
+        };
+
+        if res == -1 {
+            return Err(kill_error("pidfd_send_signal"));
+        }
+
+        Ok(())
+    }
+
+    /// Reaps the memory of a process which was killed but hasn't exited yet,
+    /// instead of waiting for it to release its memory by itself
+    pub fn release_memory(&self) -> Result<()> {
+        // Safety: `self.fd` is a valid pidfd
+        let res = unsafe { libc::syscall(SYS_PROCESS_MRELEASE, self.fd, 0) };
+        if res == -1 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for PidFd {
+    fn drop(&mut self) {
+        // Safety: `self.fd` is a file descriptor we own
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Which signal is sent to which process or process group when killing a victim
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KillPlan {
+    pub pid: u32,
+    /// The process group of the victim, only set if the whole group is to be killed
+    pub pgid: Option<i32>,
+    pub signal: i32,
+    /// Whether SIGKILL follows if the victim doesn't exit in time
+    pub escalate: bool,
+}
+
+impl KillPlan {
+    pub fn new(process: &Process, kill_pgroup: bool) -> Result<Self> {
+        let pgid = if kill_pgroup {
+            Some(utils::get_process_group(process.pid as i32)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            pid: process.pid,
+            pgid,
+            signal: SIGTERM,
+            // TODO: kill and wait for process groups as well
+            escalate: pgid.is_none(),
+        })
+    }
+}
+
+fn signal_name(signal: i32) -> String {
+    match signal {
+        SIGTERM => "SIGTERM".into(),
+        SIGKILL => "SIGKILL".into(),
+        _ => format!("signal {}", signal),
+    }
+}
+
+impl fmt::Display for KillPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} to ", signal_name(self.signal))?;
+        match self.pgid {
+            Some(pgid) => write!(f, "process group {} (of PID {})", pgid, self.pid)?,
+            None => write!(f, "PID {}", self.pid)?,
+        }
+        if self.escalate {
+            write!(f, ", then SIGKILL if it doesn't exit in time")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn kill_process_group(plan: &KillPlan) -> Result<()> {
+    let pgid = plan.pgid.ok_or(Error::ProcessGroupNotFound)?;
+
+    // TODO: kill and wait
+    kill_process(-pgid, plan.signal)
 }
 
 /// Tries to kill a process and wait for it to exit
 /// Will first send the victim a SIGTERM and escalate to SIGKILL if necessary
-/// Returns Ok(true) if the victim was successfully terminated
-pub fn kill_and_wait(process: Process) -> Result<bool> {
+/// Fails if a signal couldn't be sent or if the victim still hasn't exited after a SIGKILL
+///
+/// If `choose_victim` opened a pidfd to it, signals are sent through it, so that another
+/// process reusing the victim's PID can't be killed by mistake, and its memory is reaped
+/// right away once it's sent a SIGKILL
+pub fn kill_and_wait(process: Process, plan: &KillPlan, capabilities: &Capabilities) -> Result<()> {
     let pid = process.pid;
     let now = Instant::now();
 
-    let _ = kill_process(pid as i32, SIGTERM);
+    let pidfd = &process.pidfd;
+    let send_signal = |signal| match pidfd {
+        Some(pidfd) => pidfd.send_signal(signal),
+        None => kill_process(pid as i32, signal),
+    };
+
+    send_signal(plan.signal)?;
 
     let half_a_sec = Duration::from_secs_f32(0.5);
     let mut sigkill_sent = false;
@@ -146,11 +538,23 @@ pub fn kill_and_wait(process: Process) -> Result<bool> {
         std::thread::sleep(half_a_sec);
         if !process.is_alive() {
             println!("[LOG] Process with PID {} has exited.\n", pid);
-            return Ok(true);
+            return Ok(());
         }
         if !sigkill_sent {
-            let _ = kill_process(pid as i32, SIGKILL);
+            match send_signal(SIGKILL) {
+                // It exited right after we checked
+                Err(Error::ProcessNotFound(_)) => {}
+                res => res?,
+            }
             sigkill_sent = true;
+
+            if let (Some(pidfd), true) = (pidfd, capabilities.process_mrelease) {
+                // Fails if the victim hasn't started exiting yet, in which
+                // case it'll just release its memory by itself
+                if pidfd.release_memory().is_ok() {
+                    println!("[LOG] Reaped the memory of PID {}.", pid);
+                }
+            }
             println!(
                 "[LOG] Escalated to SIGKILL after {} nanosecs",
                 now.elapsed().as_nanos()
@@ -158,5 +562,204 @@ pub fn kill_and_wait(process: Process) -> Result<bool> {
         }
     }
 
-    Ok(false)
+    Err(Error::VictimSurvived(pid))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::{assess, pids, rank, Candidate};
+    use crate::config::Config;
+    use crate::policy::PolicyKind;
+    use crate::process::Process;
+    use crate::tier::Tier;
+
+    /// Replaces the allocator of the whole test binary, which only adds a
+    /// thread-local counter on top of the system allocator
+    mod scan_budget {
+        use std::alloc::{GlobalAlloc, Layout, System};
+        use std::cell::Cell;
+        use std::process::{Child, Command};
+        use std::time::{Duration, Instant};
+
+        use crate::capabilities::Capabilities;
+        use crate::config::Config;
+        use crate::kill::choose_victim_among;
+        use crate::policy::PolicyKind;
+        use crate::process::Process;
+        use crate::tier::Tiers;
+
+        thread_local! {
+            static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+        }
+
+        /// Counts allocations per thread, so that tests running in parallel don't skew the count
+        struct CountingAllocator;
+
+        unsafe impl GlobalAlloc for CountingAllocator {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+                System.alloc(layout)
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                System.dealloc(ptr, layout)
+            }
+        }
+
+        #[global_allocator]
+        static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+        /// How many processes a scan goes through, whatever runs on the host
+        const PROCESSES: usize = 2000;
+        /// Matching rules mostly allocates for the fields that can't be read through `buf`,
+        /// which takes 8 allocations per process as of writing
+        const MAX_ALLOCATIONS: usize = 12 * PROCESSES;
+        /// Around 40ms in debug builds as of writing, with room for slow CI machines
+        const MAX_TIME: Duration = Duration::from_secs(1);
+
+        #[test]
+        fn scan_budget() {
+            let rules = |rules: &[&str]| rules.iter().map(|rule| rule.parse().unwrap()).collect();
+            let mut config = Config {
+                tiers: Tiers {
+                    prefer: rules(&["chrom*", "cmdline:*-language-server*"]),
+                    avoid: rules(&["Xorg", "exe:/usr/lib/jvm/*", "user:root"]),
+                    protect: rules(&["sshd", "cgroup:/system.slice/sshd.service", "uid:65534"]),
+                },
+                #[cfg(feature = "glob-ignore")]
+                ignored: Some(vec![glob::Pattern::new("systemd-*").unwrap()]),
+                ..Config::default()
+            };
+
+            let mut proc_buf = [0_u8; 50];
+            let mut buf = [0_u8; 512];
+            let capabilities = Capabilities::probe(None, &mut buf);
+
+            // A handful of real processes, each seen many times over
+            let mut children: Vec<Child> = (0..8)
+                .map(|_| Command::new("sleep").arg("30").spawn().unwrap())
+                .collect();
+            let pids: Vec<u32> = children.iter().map(Child::id).collect();
+
+            // Streamed through, and ranked once every candidate was assessed
+            for policy in [PolicyKind::OomScore, PolicyKind::Uss] {
+                config.victim_policy = policy;
+                let victim_policy = policy.policy();
+
+                let allocations_before = ALLOCATIONS.with(Cell::get);
+                let now = Instant::now();
+                let processes = pids
+                    .iter()
+                    .cycle()
+                    .take(PROCESSES)
+                    .filter_map(|&pid| Process::from_pid(pid, &mut proc_buf).ok());
+                let victim = choose_victim_among(
+                    processes,
+                    &mut buf,
+                    &config,
+                    &*victim_policy,
+                    &capabilities,
+                    None,
+                )
+                .unwrap();
+                let elapsed = now.elapsed();
+                let allocations = ALLOCATIONS.with(Cell::get) - allocations_before;
+
+                println!(
+                    "{}: {} allocations for {} processes in {:?}",
+                    policy.as_str(),
+                    allocations,
+                    PROCESSES,
+                    elapsed
+                );
+                assert!(pids.contains(&victim.pid));
+                assert!(
+                    allocations <= MAX_ALLOCATIONS,
+                    "{}: {} allocations for {} processes",
+                    policy.as_str(),
+                    allocations,
+                    PROCESSES
+                );
+                assert!(
+                    elapsed <= MAX_TIME,
+                    "{}: took {:?} for {} processes",
+                    policy.as_str(),
+                    elapsed,
+                    PROCESSES
+                );
+            }
+
+            for child in &mut children {
+                child.kill().unwrap();
+                child.wait().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn rank_only_scores_the_largest_for_expensive_policies() {
+        let config = Config {
+            victim_policy: PolicyKind::Uss,
+            smaps_candidates: 2,
+            ..Config::default()
+        };
+
+        let mut proc_buf = [0_u8; 50];
+        let mut buf = [0_u8; 512];
+        let candidates: Vec<_> = pids()
+            .unwrap()
+            .filter_map(|pid| Process::from_pid(pid, &mut proc_buf).ok())
+            .filter_map(|process| assess(process, &mut buf, &config, None).ok())
+            .collect();
+        let total = candidates.len();
+
+        let policy = config.victim_policy.policy();
+        let ranked = rank(candidates, &mut buf, &config, &*policy);
+        assert_eq!(ranked.len(), total);
+        let scored = ranked
+            .iter()
+            .take_while(|(_, score)| score.is_some())
+            .count();
+        assert!(scored <= 2);
+        assert!(ranked[scored..].iter().all(|(_, score)| score.is_none()));
+        assert!(ranked[..scored].windows(2).all(|w| w[0].1 >= w[1].1));
+    }
+
+    #[test]
+    fn rank_goes_by_tier_first() {
+        let config = Config {
+            victim_policy: PolicyKind::Uss,
+            ..Config::default()
+        };
+        let mut proc_buf = [0_u8; 50];
+        let mut buf = [0_u8; 512];
+        let candidate = |process: Process, tier| Candidate {
+            process,
+            vm_rss_kib: 1024,
+            oom_score_adj: 0,
+            tier,
+        };
+
+        // Its smaps_rollup is gone by the time it's scored
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        let expendable = Process::from_pid(child.id(), &mut proc_buf).unwrap();
+        child.kill().unwrap();
+        child.wait().unwrap();
+        let ours = Process::from_pid(std::process::id(), &mut proc_buf).unwrap();
+
+        let candidates = vec![
+            candidate(ours, Tier::Normal),
+            candidate(expendable, Tier::Expendable),
+        ];
+        let policy = config.victim_policy.policy();
+        let ranked = rank(candidates, &mut buf, &config, &*policy);
+
+        // Unscored, yet ahead of a scored candidate from a later tier
+        assert_eq!(ranked[0].0.tier, Tier::Expendable);
+        assert_eq!(ranked[0].1, None);
+        assert_eq!(ranked[1].0.tier, Tier::Normal);
+        assert!(ranked[1].1.is_some());
+    }
 }
@@ -1,5 +1,113 @@
-#[derive(Debug)]
+use std::{fmt, str::FromStr};
+
+use crate::error::Error;
+
+/// A Linux release, as given by `uname -r`, e.g. `5.15.0-91-generic` or `6.1.0-rc7`
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LinuxVersion {
     pub major: u8,
     pub minor: u8,
+    pub patch: u16,
+    /// The release candidate number, if this is a pre-release
+    pub rc: Option<u8>,
+    /// Whatever the distribution appended to the version, such as `-91-generic`
+    pub suffix: String,
+}
+
+impl LinuxVersion {
+    /// Pressure Stall Information was introduced in Linux 4.20
+    pub const PSI: (u8, u8) = (4, 20);
+
+    /// Returns true if this is at least Linux `major.minor`, release candidates included.
+    ///
+    /// Features are usually already present in the release candidates of the version that
+    /// introduced them, and vendors often backport them to older versions, so this is only
+    /// ever a hint: whether something is supported should be probed for.
+    pub fn is_at_least(&self, (major, minor): (u8, u8)) -> bool {
+        (self.major, self.minor) >= (major, minor)
+    }
+
+    /// Returns true if this version is recent enough to (possibly) support PSI.
+    /// Kernels built without `CONFIG_PSI` won't, regardless
+    pub fn supports_psi(&self) -> bool {
+        self.is_at_least(Self::PSI)
+    }
+}
+
+impl FromStr for LinuxVersion {
+    type Err = Error;
+
+    fn from_str(release: &str) -> Result<Self, Self::Err> {
+        // The numbered part of the version ends with the first character that isn't a digit or a dot
+        let numbers_end = release
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(release.len());
+        let (numbers, rest) = release.split_at(numbers_end);
+
+        let mut numbers = numbers.split('.');
+        let mut next = || numbers.next().filter(|number| !number.is_empty());
+
+        let major = next().ok_or(Error::InvalidLinuxVersion)?.parse()?;
+        let minor = next().ok_or(Error::InvalidLinuxVersion)?.parse()?;
+        // Release candidates, such as `6.1-rc7`, have no patch level
+        let patch = next().map(str::parse).transpose()?.unwrap_or(0);
+
+        let (rc, suffix) = match rest.strip_prefix("-rc") {
+            Some(rc) => {
+                let rc_end = rc.find(|c: char| !c.is_ascii_digit()).unwrap_or(rc.len());
+                let (number, suffix) = rc.split_at(rc_end);
+                (Some(number.parse()?), suffix)
+            }
+            None => (None, rest),
+        };
+
+        Ok(LinuxVersion {
+            major,
+            minor,
+            patch,
+            rc,
+            suffix: suffix.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for LinuxVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if let Some(rc) = self.rc {
+            write!(f, "-rc{}", rc)?;
+        }
+        write!(f, "{}", self.suffix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LinuxVersion;
+
+    #[test]
+    fn parse() {
+        let version: LinuxVersion = "5.15.0-91-generic".parse().unwrap();
+        assert_eq!((version.major, version.minor, version.patch), (5, 15, 0));
+        assert_eq!(version.rc, None);
+        assert_eq!(version.suffix, "-91-generic");
+
+        let version: LinuxVersion = "6.1-rc7".parse().unwrap();
+        assert_eq!((version.major, version.minor, version.patch), (6, 1, 0));
+        assert_eq!(version.rc, Some(7));
+        assert_eq!(version.to_string(), "6.1.0-rc7");
+
+        let version: LinuxVersion = "3.10.0-1160.el7.x86_64".parse().unwrap();
+        assert_eq!(version.suffix, "-1160.el7.x86_64");
+        assert!(!version.supports_psi());
+
+        let version: LinuxVersion = "4.20.17+".parse().unwrap();
+        assert_eq!(version.patch, 17);
+        assert_eq!(version.suffix, "+");
+        assert!(version.supports_psi());
+
+        assert!("".parse::<LinuxVersion>().is_err());
+        assert!("6".parse::<LinuxVersion>().is_err());
+        assert!("linux".parse::<LinuxVersion>().is_err());
+    }
 }
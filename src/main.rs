@@ -2,28 +2,96 @@
 
 use uname::Uname;
 
-use crate::{memory::lock_memory_pages, monitor::Monitor};
+use crate::{
+    capabilities::Capabilities, config::Config, memory::lock_memory_pages, monitor::Monitor,
+};
 
+mod capabilities;
+mod cgroup;
 mod cli;
+mod config;
 mod daemon;
+mod doctor;
 mod errno;
 mod error;
+mod explain;
 mod kill;
 mod linux_version;
 mod memory;
 mod monitor;
+mod policy;
 mod process;
+mod rule;
+mod tier;
 mod uname;
 mod utils;
+mod warning;
+
+/// Takes a single reading, acts on it if needed and returns the exit code to report it with
+fn check_once(mut config: Config) -> i32 {
+    // A single reading can't be sustained, and there's nothing to wait for
+    config.sustain_samples = None;
+    config.sustain_ms = None;
+    config.psi_trigger = None;
+
+    let mut buf = [0_u8; 512];
+    let linux_version = Uname::new().and_then(|uname| uname.parse_version()).ok();
+    let capabilities = Capabilities::probe(linux_version, &mut buf);
+
+    let mut monitor = match Monitor::new([0_u8; 50], buf, config, capabilities) {
+        Ok(monitor) => monitor,
+        Err(err) => {
+            println!("UNKNOWN: could not read memory: {}", err);
+            return 4;
+        }
+    };
+
+    let outcome = monitor.check_once();
+    let memory_info = monitor.memory_info();
+    println!(
+        "{} (available RAM: {:.2}%, available swap: {:.2}%)",
+        outcome, memory_info.available_ram_percent, memory_info.available_swap_percent
+    );
+
+    outcome.exit_code()
+}
 
 fn main() -> error::Result<()> {
     let args: cli::CommandLineArgs = argh::from_env();
 
+    // The doctor reports a broken configuration as a failed check of its own
+    if let Some(cli::Command::Doctor(_)) = args.command {
+        let mut buf = [0_u8; 512];
+        if !doctor::run(&args, &mut buf)? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // Read the configuration before daemonizing so that
+    // errors in it are shown to whoever started `bustd`
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        // Exit codes other than 4 would be taken for the state of memory
+        Err(err) if args.once => {
+            println!("UNKNOWN: could not load the configuration: {}", err);
+            std::process::exit(4);
+        }
+        Err(err) => return Err(err),
+    };
+
+    if let Some(cli::Command::Explain(explain_args)) = &args.command {
+        return explain::run(&config, explain_args.json);
+    }
+
+    if args.once {
+        std::process::exit(check_once(config));
+    }
+
     // Show uname info and return the Linux version running
-    let _linux_version = {
+    let linux_version = {
         let uname = Uname::new()?;
         let _ = uname.print_info();
-        // TODO: check if Linux version is updated enough
         uname.parse_version().ok()
     };
 
@@ -33,7 +101,11 @@ fn main() -> error::Result<()> {
     let proc_buf = [0_u8; 50];
 
     // Buffer for anything else
-    let buf = [0_u8; 100];
+    let mut buf = [0_u8; 512];
+
+    // Check what the running kernel supports, so that we can choose our code paths accordingly
+    let capabilities = Capabilities::probe(linux_version, &mut buf);
+    println!("{}", capabilities);
 
     if !args.no_daemon {
         // Daemonize current process
@@ -44,7 +116,7 @@ fn main() -> error::Result<()> {
     // Attempt to lock the memory pages mapped to the daemon
     // in order to avoid being sent to swap when the system
     // memory is stressed
-    if let Err(err) = lock_memory_pages() {
+    if let Err(err) = lock_memory_pages(capabilities.mcl_onfault) {
         eprintln!("Failed to lock memory pages: {:?}. Continuing anyway.", err);
     } else {
         // Save this on both bustd.out and bustd.err
@@ -52,5 +124,5 @@ fn main() -> error::Result<()> {
         eprintln!("Memory pages locked!");
     }
 
-    Monitor::new(proc_buf, buf, args)?.poll()
+    Monitor::new(proc_buf, buf, config, capabilities)?.poll()
 }
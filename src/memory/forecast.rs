@@ -0,0 +1,179 @@
+use std::time::{Duration, Instant};
+
+use super::MemoryInfo;
+
+/// How many readings a [`MemoryHistory`] keeps
+const HISTORY_SAMPLES: usize = 16;
+
+/// How many readings are needed before a trend is worth trusting
+const MIN_SAMPLES: usize = 4;
+
+/// Where available memory is headed, according to recent readings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Forecast {
+    /// How fast available RAM is shrinking, in KiB/s. Negative when memory is being freed
+    pub ram_fill_rate: f64,
+    /// How fast available swap is shrinking, in KiB/s. Negative when swap is being freed
+    pub swap_fill_rate: f64,
+    /// How long until both RAM and swap run out at the current rate.
+    /// `None` if they aren't filling up at all
+    pub time_to_exhaustion: Option<Duration>,
+}
+
+impl Forecast {
+    /// Returns true if memory is projected to run out within `horizon`
+    pub fn exhausts_within(&self, horizon: Duration) -> bool {
+        self.time_to_exhaustion.is_some_and(|time| time <= horizon)
+    }
+}
+
+/// A fixed-size history of available RAM and swap, used to estimate their fill rate
+pub struct MemoryHistory {
+    /// Ring buffer of (when, available RAM, available swap)
+    samples: [Option<(Instant, u64, u64)>; HISTORY_SAMPLES],
+    /// Index of where the next sample will be stored
+    next: usize,
+}
+
+impl MemoryHistory {
+    pub fn new() -> Self {
+        Self {
+            samples: [None; HISTORY_SAMPLES],
+            next: 0,
+        }
+    }
+
+    /// Records the given readings, taken at `now`
+    pub fn record(&mut self, now: Instant, memory_info: &MemoryInfo) {
+        self.samples[self.next] = Some((
+            now,
+            memory_info.available_ram_kib,
+            memory_info.available_swap_kib,
+        ));
+        self.next = (self.next + 1) % HISTORY_SAMPLES;
+    }
+
+    /// Fits a line through the recorded readings (through least squares) and
+    /// projects when available RAM and swap will reach zero.
+    ///
+    /// Returns `None` until enough readings were recorded.
+    pub fn forecast(&self) -> Option<Forecast> {
+        let oldest = self.samples.iter().flatten().map(|s| s.0).min()?;
+        let newest = (self.next + HISTORY_SAMPLES - 1) % HISTORY_SAMPLES;
+        let (_, ram_kib, swap_kib) = self.samples[newest]?;
+
+        let count = self.samples.iter().flatten().count();
+        if count < MIN_SAMPLES {
+            return None;
+        }
+
+        // Seconds since the oldest sample, so that the numbers stay small
+        let secs = |at: Instant| at.duration_since(oldest).as_secs_f64();
+        let n = count as f64;
+        let mean_t = self
+            .samples
+            .iter()
+            .flatten()
+            .map(|s| secs(s.0))
+            .sum::<f64>()
+            / n;
+
+        let variance: f64 = self
+            .samples
+            .iter()
+            .flatten()
+            .map(|s| (secs(s.0) - mean_t).powi(2))
+            .sum();
+        if variance == 0.0 {
+            return None;
+        }
+
+        // How fast a value shrinks, i.e. the slope of the fitted line, negated
+        let shrink_rate = |value: fn(&(Instant, u64, u64)) -> u64| {
+            let mean = self
+                .samples
+                .iter()
+                .flatten()
+                .map(|s| value(s) as f64)
+                .sum::<f64>()
+                / n;
+            let covariance: f64 = self
+                .samples
+                .iter()
+                .flatten()
+                .map(|s| (secs(s.0) - mean_t) * (mean - value(s) as f64))
+                .sum();
+            covariance / variance
+        };
+
+        // Available memory going down means memory filling up
+        let ram_fill_rate = shrink_rate(|s| s.1);
+        let swap_fill_rate = shrink_rate(|s| s.2);
+
+        let fill_rate = ram_fill_rate + swap_fill_rate;
+        let time_to_exhaustion = if fill_rate > 0.0 {
+            Some(Duration::from_secs_f64(
+                (ram_kib + swap_kib) as f64 / fill_rate,
+            ))
+        } else {
+            None
+        };
+
+        Some(Forecast {
+            ram_fill_rate,
+            swap_fill_rate,
+            time_to_exhaustion,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::MemoryHistory;
+    use crate::memory::MemoryInfo;
+
+    fn reading(available_ram_kib: u64, available_swap_kib: u64) -> MemoryInfo {
+        MemoryInfo {
+            available_ram_kib,
+            available_swap_kib,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn forecast() {
+        let start = Instant::now();
+        let mut history = MemoryHistory::new();
+
+        // Losing 100 MiB of RAM and 10 MiB of swap every 100ms
+        for i in 0..5 {
+            let at = start + Duration::from_millis(100 * i);
+            history.record(at, &reading(1_000_000 - 102_400 * i, 200_000 - 10_240 * i));
+            if i < 3 {
+                assert!(history.forecast().is_none());
+            }
+        }
+
+        let forecast = history.forecast().unwrap();
+        assert!((forecast.ram_fill_rate - 1_024_000.0).abs() < 1.0);
+        assert!((forecast.swap_fill_rate - 102_400.0).abs() < 1.0);
+
+        // 590400 KiB of RAM and 159040 KiB of swap left, at 1126400 KiB/s
+        let time_to_exhaustion = forecast.time_to_exhaustion.unwrap();
+        assert_eq!(time_to_exhaustion.as_millis(), 665);
+        assert!(forecast.exhausts_within(Duration::from_secs(1)));
+        assert!(!forecast.exhausts_within(Duration::from_millis(500)));
+
+        // Memory being freed never runs out
+        let mut history = MemoryHistory::new();
+        for i in 0..5 {
+            let at = start + Duration::from_millis(100 * i);
+            history.record(at, &reading(100_000 + 1000 * i, 0));
+        }
+        let forecast = history.forecast().unwrap();
+        assert!(forecast.ram_fill_rate < 0.0);
+        assert_eq!(forecast.time_to_exhaustion, None);
+    }
+}
@@ -1,20 +1,55 @@
-use std::{fmt, mem};
+use std::{fmt, fs::File, mem, str::FromStr};
 
 use libc::sysinfo;
 
 use crate::{
+    cgroup,
     error::{Error, Result},
-    utils::bytes_to_megabytes,
+    utils::{bytes_to_kib, for_each_line},
 };
 
+/// Where memory readings are taken from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemorySource {
+    /// The `sysinfo` syscall. Fast, but its free RAM does not account for reclaimable page cache
+    Sysinfo,
+    /// `/proc/meminfo`, whose `MemAvailable` accounts for reclaimable memory
+    Meminfo,
+}
+
+impl FromStr for MemorySource {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "sysinfo" => Ok(Self::Sysinfo),
+            "meminfo" => Ok(Self::Meminfo),
+            _ => Err(format!(
+                "unknown memory source `{}`, expected `sysinfo` or `meminfo`",
+                s
+            )),
+        }
+    }
+}
+
+/// Memory readings, in KiB
 #[derive(Debug, Default)]
 pub struct MemoryInfo {
-    pub total_ram_mb: u64,
-    pub total_swap_mb: u64,
-    pub available_ram_mb: u64,
-    pub available_swap_mb: u64,
-    pub available_ram_percent: u8,
-    pub available_swap_percent: u8,
+    pub total_ram_kib: u64,
+    pub total_swap_kib: u64,
+    pub available_ram_kib: u64,
+    pub available_swap_kib: u64,
+    pub available_ram_percent: f64,
+    pub available_swap_percent: f64,
+    /// RAM that is not used at all, not even for caches
+    pub free_ram_kib: u64,
+    pub buffers_kib: u64,
+    /// Page cache. Unavailable through `sysinfo`
+    pub cached_kib: u64,
+    /// Shared memory, which lives in the page cache but can't be dropped
+    pub shmem_kib: u64,
+    /// Swapped out memory that is also in RAM. Unavailable through `sysinfo`
+    pub swap_cached_kib: u64,
 }
 
 /// Simple wrapper over libc's sysinfo
@@ -37,54 +72,256 @@ fn sys_info() -> Result<sysinfo> {
 }
 
 impl MemoryInfo {
-    pub fn new() -> Result<MemoryInfo> {
+    /// Reads the current memory stats from the given source.
+    ///
+    /// `buf` is only used when reading from `/proc/meminfo`.
+    pub fn new(source: MemorySource, buf: &mut [u8]) -> Result<MemoryInfo> {
+        let mut memory_info = match source {
+            MemorySource::Sysinfo => Self::from_sysinfo()?,
+            MemorySource::Meminfo => Self::from_meminfo(buf)?,
+        };
+
+        memory_info.update_percentages();
+
+        Ok(memory_info)
+    }
+
+    fn from_sysinfo() -> Result<MemoryInfo> {
         let sysinfo {
             mem_unit,
             freeram,
             totalram,
             totalswap,
             freeswap,
+            sharedram,
+            bufferram,
             ..
         } = sys_info()?;
 
-        let ratio = |x, y| ((x as f32 / y as f32) * 100.0) as u8;
+        let available_ram_kib = bytes_to_kib(freeram, mem_unit);
 
-        let available_ram_mb = bytes_to_megabytes(freeram, mem_unit);
-        let total_ram_mb = bytes_to_megabytes(totalram, mem_unit);
-        let total_swap_mb = bytes_to_megabytes(totalswap, mem_unit);
-        let available_swap_mb = bytes_to_megabytes(freeswap, mem_unit);
+        Ok(MemoryInfo {
+            total_ram_kib: bytes_to_kib(totalram, mem_unit),
+            available_ram_kib,
+            total_swap_kib: bytes_to_kib(totalswap, mem_unit),
+            available_swap_kib: bytes_to_kib(freeswap, mem_unit),
+            free_ram_kib: available_ram_kib,
+            buffers_kib: bytes_to_kib(bufferram, mem_unit),
+            shmem_kib: bytes_to_kib(sharedram, mem_unit),
+            ..Default::default()
+        })
+    }
 
-        let available_ram_percent = ratio(available_ram_mb, total_ram_mb);
-        let available_swap_percent = if total_swap_mb != 0 {
-            ratio(available_swap_mb, total_swap_mb)
-        } else {
-            0
+    /// Reads memory stats from `/proc/meminfo`, whose lines look like:
+    /// ```MemAvailable:    8105468 kB```
+    fn from_meminfo(buf: &mut [u8]) -> Result<MemoryInfo> {
+        let file = File::open("/proc/meminfo")?;
+
+        let mut memory_info = MemoryInfo::default();
+        let mut mem_available = None;
+        let mut malformed = false;
+
+        for_each_line(file, buf, |line| {
+            let (key, value) = match line.split_once(':') {
+                Some(key_value) => key_value,
+                None => return,
+            };
+
+            let field = match key {
+                "MemTotal" => &mut memory_info.total_ram_kib,
+                "MemFree" => &mut memory_info.free_ram_kib,
+                "Buffers" => &mut memory_info.buffers_kib,
+                "Cached" => &mut memory_info.cached_kib,
+                "Shmem" => &mut memory_info.shmem_kib,
+                "SwapCached" => &mut memory_info.swap_cached_kib,
+                "SwapTotal" => &mut memory_info.total_swap_kib,
+                "SwapFree" => &mut memory_info.available_swap_kib,
+                "MemAvailable" => mem_available.get_or_insert(0),
+                _ => return,
+            };
+
+            // Values are given in KiB, even though the file says `kB`
+            match value.split_ascii_whitespace().next().map(str::parse::<u64>) {
+                Some(Ok(kib)) => *field = kib,
+                _ => malformed = true,
+            }
+        })?;
+
+        if malformed || memory_info.total_ram_kib == 0 {
+            return Err(Error::MalformedMeminfo);
+        }
+
+        // `MemAvailable` only exists since Linux 3.14, so
+        // we'll estimate it the way `free` used to otherwise
+        memory_info.available_ram_kib = mem_available
+            .unwrap_or(memory_info.free_ram_kib + memory_info.buffers_kib + memory_info.cached_kib);
+
+        Ok(memory_info)
+    }
+
+    /// Reads memory stats from the given cgroup (relative to the root of the hierarchy),
+    /// treating its `memory.max` as the total amount of RAM.
+    ///
+    /// Limits that aren't set (such as a `memory.swap.max` of `max`) fall back to the host's.
+    pub fn from_cgroup(cgroup: &str, buf: &mut [u8]) -> Result<MemoryInfo> {
+        Self::from_cgroup_dir(&cgroup::dir_of(cgroup), &Self::from_sysinfo()?, buf)
+    }
+
+    /// Reads memory stats from the cgroup directory `dir`, capped by those of the `host`
+    fn from_cgroup_dir(dir: &str, host: &MemoryInfo, buf: &mut [u8]) -> Result<MemoryInfo> {
+        let to_kib = |bytes: u64| bytes_to_kib(bytes, 1_u32);
+
+        let current = cgroup::read_value(&format!("{}/memory.current", dir), buf)?
+            .ok_or(Error::MalformedCgroupFile)?;
+        let total_ram_kib = match cgroup::read_value(&format!("{}/memory.max", dir), buf)? {
+            Some(max) => u64::min(to_kib(max), host.total_ram_kib),
+            None => host.total_ram_kib,
         };
 
-        Ok(MemoryInfo {
-            total_ram_mb,
-            available_ram_mb,
-            total_swap_mb,
-            available_swap_mb,
-            available_ram_percent,
-            available_swap_percent,
-        })
+        let mut memory_info = MemoryInfo {
+            total_ram_kib,
+            free_ram_kib: total_ram_kib.saturating_sub(to_kib(current)),
+            ..Default::default()
+        };
+
+        // Lines look like `inactive_file 4096`, in bytes
+        let mut inactive_file_kib = 0;
+        let file = File::open(format!("{}/memory.stat", dir))?;
+        for_each_line(file, buf, |line| {
+            let (key, value) = match line.split_once(' ') {
+                Some((key, value)) => (key, value.trim().parse().unwrap_or(0)),
+                None => return,
+            };
+
+            match key {
+                "file" => memory_info.cached_kib = to_kib(value),
+                "shmem" => memory_info.shmem_kib = to_kib(value),
+                "inactive_file" => inactive_file_kib = to_kib(value),
+                _ => {}
+            }
+        })?;
+
+        // Much like `MemAvailable`, consider inactive page cache as reclaimable
+        memory_info.available_ram_kib =
+            u64::min(memory_info.free_ram_kib + inactive_file_kib, total_ram_kib);
+
+        // Swap files only exist if the kernel was built with swap support
+        let swap_max = format!("{}/memory.swap.max", dir);
+        let swap_current = format!("{}/memory.swap.current", dir);
+        let (swap_max, swap_current) = match (
+            cgroup::read_value(&swap_max, buf),
+            cgroup::read_value(&swap_current, buf),
+        ) {
+            (Ok(max), Ok(Some(current))) => (max, current),
+            _ => (None, 0),
+        };
+
+        memory_info.total_swap_kib = match swap_max {
+            Some(max) => u64::min(to_kib(max), host.total_swap_kib),
+            None => host.total_swap_kib,
+        };
+        memory_info.available_swap_kib = u64::min(
+            memory_info
+                .total_swap_kib
+                .saturating_sub(to_kib(swap_current)),
+            host.available_swap_kib,
+        );
+
+        memory_info.update_percentages();
+
+        Ok(memory_info)
+    }
+
+    fn update_percentages(&mut self) {
+        let ratio = |x, y| x as f64 / y as f64 * 100.0;
+
+        self.available_ram_percent = ratio(self.available_ram_kib, self.total_ram_kib);
+        self.available_swap_percent = if self.total_swap_kib != 0 {
+            ratio(self.available_swap_kib, self.total_swap_kib)
+        } else {
+            0.0
+        };
     }
 }
 
 impl fmt::Display for MemoryInfo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "Total RAM: {} MB", self.total_ram_mb)?;
+        writeln!(f, "Total RAM: {} KiB", self.total_ram_kib)?;
         writeln!(
             f,
-            "Available RAM: {} MB ({}%)",
-            self.available_ram_mb, self.available_ram_percent
+            "Available RAM: {} KiB ({:.2}%)",
+            self.available_ram_kib, self.available_ram_percent
         )?;
-        writeln!(f, "Total swap: {} MB", self.total_swap_mb)?;
+        writeln!(f, "Cached: {} KiB", self.cached_kib)?;
+        writeln!(f, "Total swap: {} KiB", self.total_swap_kib)?;
         writeln!(
             f,
-            "Available swap: {} MB ({} %)",
-            self.available_swap_mb, self.available_swap_percent
+            "Available swap: {} KiB ({:.2}%)",
+            self.available_swap_kib, self.available_swap_percent
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{MemoryInfo, MemorySource};
+
+    #[test]
+    fn cgroup_limits() {
+        let dir = std::env::temp_dir().join(format!("bustd-mem-info-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let gib = 1024 * 1024 * 1024_u64;
+        fs::write(dir.join("memory.current"), format!("{}\n", 3 * gib / 4)).unwrap();
+        fs::write(dir.join("memory.max"), format!("{}\n", gib)).unwrap();
+        fs::write(
+            dir.join("memory.stat"),
+            format!(
+                "anon 1024\nfile {}\ninactive_file {}\nshmem 0\n",
+                gib / 2,
+                gib / 4
+            ),
+        )
+        .unwrap();
+
+        let host = MemoryInfo {
+            total_ram_kib: 16 * 1024 * 1024,
+            total_swap_kib: 1024 * 1024,
+            available_swap_kib: 512 * 1024,
+            ..MemoryInfo::default()
+        };
+        let mut buf = [0_u8; 100];
+        let memory_info =
+            MemoryInfo::from_cgroup_dir(dir.to_str().unwrap(), &host, &mut buf).unwrap();
+
+        // `memory.max` is the total, and inactive page cache counts as available
+        assert_eq!(memory_info.total_ram_kib, 1024 * 1024);
+        assert_eq!(memory_info.free_ram_kib, 256 * 1024);
+        assert_eq!(memory_info.available_ram_kib, 512 * 1024);
+        assert_eq!(memory_info.available_ram_percent, 50.0);
+        // Without swap limits, the host's apply
+        assert_eq!(memory_info.total_swap_kib, 1024 * 1024);
+        assert_eq!(memory_info.available_swap_kib, 512 * 1024);
+
+        // Limits above what the host has are capped
+        fs::write(dir.join("memory.max"), format!("{}\n", 64 * gib)).unwrap();
+        let memory_info =
+            MemoryInfo::from_cgroup_dir(dir.to_str().unwrap(), &host, &mut buf).unwrap();
+        assert_eq!(memory_info.total_ram_kib, host.total_ram_kib);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn meminfo_totals() {
+        // Small on purpose: `/proc/meminfo` has to be read line by line
+        let mut buf = [0_u8; 100];
+        let memory_info = MemoryInfo::new(MemorySource::Meminfo, &mut buf).unwrap();
+
+        let meminfo = procfs::Meminfo::new().unwrap();
+
+        assert_eq!(memory_info.total_ram_kib, meminfo.mem_total / 1024);
+        assert_eq!(memory_info.total_swap_kib, meminfo.swap_total / 1024);
+    }
+}
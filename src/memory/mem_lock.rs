@@ -6,7 +6,13 @@ use crate::errno::errno;
 use crate::error::{Error, Result};
 
 extern "C" {
-    pub static _MCL_ONFAULT: libc::c_int;
+    static _MCL_ONFAULT: libc::c_int;
+}
+
+/// `MCL_ONFAULT` as defined by the C library, or -1 if it isn't
+pub fn mcl_onfault() -> c_int {
+    // Safety: `_MCL_ONFAULT` is a constant defined in `cc/helper.c`
+    unsafe { _MCL_ONFAULT }
 }
 
 pub fn _mlockall_wrapper(flags: c_int) -> Result<()> {
@@ -34,16 +40,16 @@ pub fn _mlockall_wrapper(flags: c_int) -> Result<()> {
     })
 }
 
-pub fn lock_memory_pages() -> Result<()> {
-    // TODO: check for _MCL_ONFAULT == -1
-
-    #[allow(non_snake_case)]
-    let MCL_ONFAULT: c_int = unsafe { _MCL_ONFAULT };
-    match _mlockall_wrapper(MCL_CURRENT | MCL_FUTURE | MCL_ONFAULT) {
-        Err(err) => {
-            eprintln!("First try at mlockall failed: {:?}", err);
+/// Locks every page mapped into our address space. With `onfault`, pages are only
+/// locked once they're faulted in, instead of all being populated right away
+pub fn lock_memory_pages(onfault: bool) -> Result<()> {
+    if onfault {
+        match _mlockall_wrapper(MCL_CURRENT | MCL_FUTURE | mcl_onfault()) {
+            Err(err) => {
+                eprintln!("First try at mlockall failed: {:?}", err);
+            }
+            Ok(_) => return Ok(()),
         }
-        Ok(_) => return Ok(()),
     }
 
     _mlockall_wrapper(MCL_CURRENT | MCL_FUTURE)
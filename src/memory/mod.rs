@@ -1,6 +1,10 @@
+mod forecast;
 mod mem_info;
 mod mem_lock;
 pub mod pressure;
+mod threshold;
 
-pub use mem_info::MemoryInfo;
-pub use mem_lock::lock_memory_pages;
+pub use forecast::{Forecast, MemoryHistory};
+pub use mem_info::{MemoryInfo, MemorySource};
+pub use mem_lock::{lock_memory_pages, mcl_onfault};
+pub use threshold::Threshold;
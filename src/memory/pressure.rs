@@ -1,8 +1,17 @@
-use std::fs::File;
-use std::io::Read;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
+use libc::{pollfd, EINTR, O_NONBLOCK, POLLERR, POLLPRI};
+
+use crate::errno::errno;
 use crate::error::{Error, Result};
-use crate::utils::str_from_u8;
+use crate::utils::for_each_line;
 
 macro_rules! malformed {
     () => {
@@ -10,35 +19,510 @@ macro_rules! malformed {
     };
 }
 
-/// Returns the avg10 value in the `some` row of `/proc/pressure/memory`, which
-/// indicates the absolute stall time (in us) in which at least some tasks were stalled.
-///
-/// The data we're reading looks like:
+/// The system-wide memory pressure file
+pub const MEMORY_PRESSURE: &str = "/proc/pressure/memory";
+
+/// One row of a pressure file, such as
 /// ```some avg10=0.00 avg60=0.00 avg300=0.00 total=0```
 ///
-pub fn pressure_some_avg10(buf: &mut [u8]) -> Result<f32> {
-    let mut file = File::open("/proc/pressure/memory")?;
-    buf.fill(0);
-
-    // `buf` won't be large enough to fit all of `/proc/pressure/memory`
-    // but will be large enough to hold at least the first line, which has the datas we want
-    let _ = file.read(buf)?;
-    let contents = str_from_u8(buf)?;
-    let line = contents.lines().next().ok_or(malformed!())?;
+/// The averages are the percentage of time in which tasks were stalled in the last
+/// 10, 60 and 300 seconds, while `total` is the absolute stall time, in us.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PressureLine {
+    pub avg10: f32,
+    pub avg60: f32,
+    pub avg300: f32,
+    pub total: u64,
+    /// Percentage of time stalled over a custom window, computed from `total` by
+    /// [`StallWindow`]. Not part of the pressure file, so `None` until recorded
+    pub window: Option<f32>,
+}
+
+/// The full contents of a pressure file
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PressureSnapshot {
+    /// Stall information for when at least some tasks were stalled
+    pub some: PressureLine,
+    /// Stall information for when all non-idle tasks were stalled simultaneously
+    pub full: PressureLine,
+}
+
+impl PressureSnapshot {
+    /// Reads a pressure file, such as `/proc/pressure/memory`.
+    ///
+    /// `buf` only needs to be large enough to hold a single line.
+    pub fn from_file(path: &str, buf: &mut [u8]) -> Result<Self> {
+        let file = File::open(path)?;
+
+        let mut some = None;
+        let mut full = None;
+        let mut malformed = false;
+
+        for_each_line(file, buf, |line| match parse_line(line) {
+            Some((PressureKind::Some, parsed)) => some = Some(parsed),
+            Some((PressureKind::Full, parsed)) => full = Some(parsed),
+            None => malformed = true,
+        })?;
+
+        match (some, full) {
+            (Some(some), Some(full)) if !malformed => Ok(Self { some, full }),
+            _ => Err(malformed!()),
+        }
+    }
+
+    pub fn line(&self, kind: PressureKind) -> &PressureLine {
+        match kind {
+            PressureKind::Some => &self.some,
+            PressureKind::Full => &self.full,
+        }
+    }
+}
+
+/// Parses a line of the form
+/// ```some avg10=0.00 avg60=0.00 avg300=0.00 total=0```
+fn parse_line(line: &str) -> Option<(PressureKind, PressureLine)> {
     let mut words = line.split_ascii_whitespace();
-    if let Some(indicator) = words.next() {
-        // This has to be the case but checking to be sure
-        if indicator == "some" {
-            let entry = words.next().ok_or(malformed!())?;
+    let kind = words.next()?.parse().ok()?;
+
+    let mut parsed = PressureLine::default();
+    for entry in words {
+        // Every entry is of the form `key=value`
+        let (key, value) = entry.split_once('=')?;
+        match key {
+            "avg10" => parsed.avg10 = value.parse().ok()?,
+            "avg60" => parsed.avg60 = value.parse().ok()?,
+            "avg300" => parsed.avg300 = value.parse().ok()?,
+            "total" => parsed.total = value.parse().ok()?,
+            // Ignore whatever newer kernels may add
+            _ => {}
+        }
+    }
+
+    Some((kind, parsed))
+}
+
+/// A field of a pressure line that can be compared against a threshold
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PressureField {
+    Avg10,
+    Avg60,
+    Avg300,
+    /// The stall percentage over the window of a [`StallWindow`]
+    Window,
+}
+
+impl FromStr for PressureField {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "avg10" => Ok(Self::Avg10),
+            "avg60" => Ok(Self::Avg60),
+            "avg300" => Ok(Self::Avg300),
+            "window" => Ok(Self::Window),
+            _ => Err(format!(
+                "expected `avg10`, `avg60`, `avg300` or `window`, found `{}`",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PressureField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let field = match self {
+            PressureField::Avg10 => "avg10",
+            PressureField::Avg60 => "avg60",
+            PressureField::Avg300 => "avg300",
+            PressureField::Window => "window",
+        };
+        write!(f, "{}", field)
+    }
+}
+
+/// A condition on a pressure snapshot, written as `<some|full> <field> <operator> <value>`,
+/// e.g. `full avg10 > 10` or `some avg60 >= 40`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PressureCondition {
+    pub kind: PressureKind,
+    pub field: PressureField,
+    /// If true, the condition is also met when the value equals the threshold
+    pub inclusive: bool,
+    pub threshold: f32,
+}
+
+impl PressureCondition {
+    /// The condition `bustd` has always used: `some avg10 >= cutoff`
+    pub fn some_avg10(cutoff: f32) -> Self {
+        Self {
+            kind: PressureKind::Some,
+            field: PressureField::Avg10,
+            inclusive: true,
+            threshold: cutoff,
+        }
+    }
+
+    /// Returns the value of the snapshot this condition looks at.
+    /// Only `None` for windowed conditions when not enough samples were recorded
+    pub fn value(&self, snapshot: &PressureSnapshot) -> Option<f32> {
+        let line = snapshot.line(self.kind);
+        match self.field {
+            PressureField::Avg10 => Some(line.avg10),
+            PressureField::Avg60 => Some(line.avg60),
+            PressureField::Avg300 => Some(line.avg300),
+            PressureField::Window => line.window,
+        }
+    }
+
+    pub fn is_met(&self, snapshot: &PressureSnapshot) -> bool {
+        match self.value(snapshot) {
+            Some(value) if self.inclusive => value >= self.threshold,
+            Some(value) => value > self.threshold,
+            None => false,
+        }
+    }
+}
+
+impl FromStr for PressureCondition {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut words = s.split_ascii_whitespace();
+        let mut next = || {
+            words.next().ok_or_else(|| {
+                "expected `<some|full> <avg10|avg60|avg300|window> <>|>=> <value>`".to_string()
+            })
+        };
+
+        let kind = next()?.parse()?;
+        let field = next()?.parse()?;
+        let inclusive = match next()? {
+            ">" => false,
+            ">=" => true,
+            operator => return Err(format!("expected `>` or `>=`, found `{}`", operator)),
+        };
+        let threshold: f32 = next()?
+            .parse()
+            .map_err(|_| "invalid threshold".to_string())?;
 
-            // The entry is of the form `avg10=0.00`
-            // We'll break this string in two in order to parse the value on the right-hand side
-            let equals_pos = entry.find('=').ok_or(malformed!())?;
-            let avg10 = entry.get(equals_pos + 1..).ok_or(malformed!())?;
-            let avg10: f32 = avg10.trim().parse()?;
-            return Ok(avg10);
+        if !(0.0..=100.0).contains(&threshold) {
+            return Err("the threshold must be between 0 and 100".into());
+        }
+
+        Ok(Self {
+            kind,
+            field,
+            inclusive,
+            threshold,
+        })
+    }
+}
+
+impl fmt::Display for PressureCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.kind.as_str(),
+            self.field,
+            if self.inclusive { ">=" } else { ">" },
+            self.threshold
+        )
+    }
+}
+
+/// Which of the rows of a pressure file to look at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PressureKind {
+    /// Time in which at least some tasks were stalled
+    Some,
+    /// Time in which all non-idle tasks were stalled simultaneously
+    Full,
+}
+
+impl PressureKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PressureKind::Some => "some",
+            PressureKind::Full => "full",
+        }
+    }
+}
+
+impl FromStr for PressureKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "some" => Ok(Self::Some),
+            "full" => Ok(Self::Full),
+            _ => Err(format!("expected `some` or `full`, found `{}`", s)),
         }
     }
+}
 
-    Err(malformed!())
+/// How many samples a [`StallWindow`] keeps
+const STALL_SAMPLES: usize = 16;
+
+/// Computes the stall percentage over an arbitrary window from the deltas of the
+/// `total` counters of consecutive snapshots.
+///
+/// The kernel's averages are smoothed heavily and take several seconds to reflect
+/// a sudden spike, while the `total` counters are updated right away.
+pub struct StallWindow {
+    window: Duration,
+    /// Ring buffer of (when, some total, full total)
+    samples: [Option<(Instant, u64, u64)>; STALL_SAMPLES],
+    /// Index of where the next sample will be stored
+    next: usize,
+}
+
+impl StallWindow {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: [None; STALL_SAMPLES],
+            next: 0,
+        }
+    }
+
+    /// Records `snapshot` as taken at `now` and fills in its `window` fields
+    pub fn record(&mut self, now: Instant, snapshot: &mut PressureSnapshot) {
+        let newest = (self.next + STALL_SAMPLES - 1) % STALL_SAMPLES;
+        if let Some((taken_at, _, _)) = self.samples[newest] {
+            // Snapshots aren't taken while memory is plentiful. Don't let a
+            // stale sample average a recent spike out over a long time
+            if now.duration_since(taken_at) > self.window * 2 {
+                self.samples = [None; STALL_SAMPLES];
+            }
+        }
+
+        self.samples[self.next] = Some((now, snapshot.some.total, snapshot.full.total));
+        self.next = (self.next + 1) % STALL_SAMPLES;
+
+        // The sample to compare against is the newest one that is at least `window`
+        // old or, if there's none, the oldest one we have
+        let baseline = self
+            .samples
+            .iter()
+            .flatten()
+            .filter(|(taken_at, _, _)| *taken_at < now)
+            .filter(|(taken_at, _, _)| now.duration_since(*taken_at) >= self.window)
+            .max_by_key(|(taken_at, _, _)| *taken_at)
+            .or_else(|| {
+                self.samples
+                    .iter()
+                    .flatten()
+                    .filter(|(taken_at, _, _)| *taken_at < now)
+                    .min_by_key(|(taken_at, _, _)| *taken_at)
+            });
+
+        let (taken_at, some_total, full_total) = match baseline {
+            Some(baseline) => *baseline,
+            None => {
+                snapshot.some.window = None;
+                snapshot.full.window = None;
+                return;
+            }
+        };
+
+        let elapsed_us = now.duration_since(taken_at).as_micros() as f32;
+        let percent = |total: u64, previous: u64| {
+            let stalled_us = total.saturating_sub(previous) as f32;
+            f32::min(stalled_us * 100.0 / elapsed_us, 100.0)
+        };
+
+        snapshot.some.window = Some(percent(snapshot.some.total, some_total));
+        snapshot.full.window = Some(percent(snapshot.full.total, full_total));
+    }
+}
+
+/// Describes a PSI trigger, which fires when the stall time of `kind`
+/// reaches `stall_us` within any `window_us` long time window.
+///
+/// Written as `<some|full> <stall us> <window us>`, just like the kernel expects it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TriggerSpec {
+    pub kind: PressureKind,
+    pub stall_us: u64,
+    pub window_us: u64,
+}
+
+impl FromStr for TriggerSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut words = s.split_ascii_whitespace();
+        let mut next = || {
+            words
+                .next()
+                .ok_or_else(|| "expected `<some|full> <stall us> <window us>`".to_string())
+        };
+
+        let kind = next()?.parse()?;
+        let stall_us = next()?
+            .parse()
+            .map_err(|_| "invalid stall time".to_string())?;
+        let window_us = next()?.parse().map_err(|_| "invalid window".to_string())?;
+
+        // The limits imposed by the kernel
+        if !(500_000..=10_000_000).contains(&window_us) {
+            return Err("the window must be between 500000 and 10000000 us".into());
+        }
+        if stall_us == 0 || stall_us > window_us {
+            return Err("the stall time must be greater than zero and fit in the window".into());
+        }
+
+        Ok(Self {
+            kind,
+            stall_us,
+            window_us,
+        })
+    }
+}
+
+/// A PSI trigger registered on a memory pressure file, such as
+/// `/proc/pressure/memory` or the `memory.pressure` of a cgroup.
+///
+/// The trigger stays active for as long as this struct lives.
+pub struct PressureTrigger {
+    file: File,
+}
+
+impl PressureTrigger {
+    pub fn new(path: &str, spec: TriggerSpec, buf: &mut [u8]) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(O_NONBLOCK)
+            .open(path)?;
+
+        buf.fill(0);
+        // The kernel expects the trigger to be NUL-terminated
+        write!(
+            &mut *buf,
+            "{} {} {}\0",
+            spec.kind.as_str(),
+            spec.stall_us,
+            spec.window_us
+        )?;
+        let len = buf.iter().position(|&c| c == b'\0').unwrap_or(0) + 1;
+        file.write_all(&buf[..len])?;
+
+        Ok(Self { file })
+    }
+
+    /// Blocks until the trigger fires or `timeout` elapses.
+    ///
+    /// Returns Ok(true) if the trigger fired.
+    pub fn wait(&self, timeout: Duration) -> Result<bool> {
+        let mut fds = pollfd {
+            fd: self.file.as_raw_fd(),
+            events: POLLPRI,
+            revents: 0,
+        };
+
+        // A negative timeout would wait forever
+        let timeout_ms = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+
+        // Safety: `fds` is a valid pollfd and we're passing nfds = 1
+        let ret_val = unsafe { libc::poll(&mut fds, 1, timeout_ms) };
+
+        match ret_val {
+            // Interrupted by a signal, which is fine: we'll just poll again later
+            -1 if errno() == EINTR => Ok(false),
+            -1 => Err(std::io::Error::last_os_error().into()),
+            0 => Ok(false),
+            _ if fds.revents & POLLERR != 0 => Err(Error::PressureTriggerGone),
+            _ => Ok(fds.revents & POLLPRI != 0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{
+        PressureCondition, PressureKind, PressureSnapshot, StallWindow, TriggerSpec,
+        MEMORY_PRESSURE,
+    };
+
+    #[test]
+    fn snapshot() {
+        let mut buf = [0_u8; 100];
+        let snapshot = PressureSnapshot::from_file(MEMORY_PRESSURE, &mut buf).unwrap();
+
+        let pressure = procfs::MemoryPressure::new().unwrap();
+
+        // Stall totals only ever grow, and `procfs` read them after we did
+        assert!(pressure.some.total >= snapshot.some.total);
+        assert!(pressure.full.total >= snapshot.full.total);
+    }
+
+    #[test]
+    fn trigger_spec() {
+        let spec: TriggerSpec = "some 150000 2000000".parse().unwrap();
+        assert_eq!(
+            spec,
+            TriggerSpec {
+                kind: PressureKind::Some,
+                stall_us: 150_000,
+                window_us: 2_000_000,
+            }
+        );
+
+        // The window must be within the kernel's limits
+        assert!("some 150000 499999".parse::<TriggerSpec>().is_err());
+        assert!("full 150000 10000001".parse::<TriggerSpec>().is_err());
+        assert!("full 500000 500000".parse::<TriggerSpec>().is_ok());
+        assert!("full 10000000 10000000".parse::<TriggerSpec>().is_ok());
+        // And fit the stall time
+        assert!("some 0 1000000".parse::<TriggerSpec>().is_err());
+        assert!("some 1000001 1000000".parse::<TriggerSpec>().is_err());
+
+        assert!("avg10 150000 1000000".parse::<TriggerSpec>().is_err());
+        assert!("some 150000".parse::<TriggerSpec>().is_err());
+        assert!("some -1 1000000".parse::<TriggerSpec>().is_err());
+    }
+
+    #[test]
+    fn condition() {
+        let condition: PressureCondition = "full avg10 > 10".parse().unwrap();
+
+        let mut snapshot = PressureSnapshot::default();
+        snapshot.some.avg10 = 50.0;
+        snapshot.full.avg10 = 10.0;
+        assert!(!condition.is_met(&snapshot));
+
+        snapshot.full.avg10 = 10.5;
+        assert!(condition.is_met(&snapshot));
+
+        assert!("full avg10 < 10".parse::<PressureCondition>().is_err());
+        assert!("some total > 10".parse::<PressureCondition>().is_err());
+    }
+
+    #[test]
+    fn stall_window() {
+        let mut window = StallWindow::new(Duration::from_secs(1));
+        let start = Instant::now();
+
+        let mut snapshot = PressureSnapshot::default();
+        window.record(start, &mut snapshot);
+        // A single sample is not enough to compute anything
+        assert_eq!(snapshot.some.window, None);
+
+        // 300ms of stall in the last 500ms
+        snapshot.some.total = 300_000;
+        window.record(start + Duration::from_millis(500), &mut snapshot);
+        assert_eq!(snapshot.some.window, Some(60.0));
+
+        // Only the last second counts: 100ms of stall since the previous sample
+        snapshot.some.total = 400_000;
+        window.record(start + Duration::from_millis(1500), &mut snapshot);
+        assert_eq!(snapshot.some.window, Some(10.0));
+
+        let condition: PressureCondition = "some window >= 10".parse().unwrap();
+        assert!(condition.is_met(&snapshot));
+    }
 }
@@ -0,0 +1,139 @@
+use std::{fmt, str::FromStr};
+
+/// A limit on available memory (or swap), given either as a percentage
+/// of the total or as an absolute size.
+///
+/// Written as `10%` (or just `10`), as a size such as `512M` or `2GiB`,
+/// or as `min(10%, 2G)`, meaning whichever of the two is lower.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Threshold {
+    Percent(f64),
+    /// Absolute size, in KiB
+    Size(u64),
+    /// Whichever of the percentage and the size (in KiB) is lower
+    MinOf(f64, u64),
+}
+
+impl Threshold {
+    /// Returns this threshold in KiB, given the total amount of memory in KiB
+    pub fn kib(&self, total_kib: u64) -> u64 {
+        let from_percent = |percent: f64| (total_kib as f64 * percent / 100.0) as u64;
+
+        match *self {
+            Threshold::Percent(percent) => from_percent(percent),
+            Threshold::Size(kib) => kib,
+            Threshold::MinOf(percent, kib) => u64::min(from_percent(percent), kib),
+        }
+    }
+
+    /// Returns true if `available_kib` out of `total_kib` is at or below this threshold
+    pub fn is_met(&self, available_kib: u64, total_kib: u64) -> bool {
+        available_kib <= self.kib(total_kib)
+    }
+}
+
+fn parse_percent(s: &str) -> Result<f64, String> {
+    let percent: f64 = s
+        .trim_end_matches('%')
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid percentage `{}`", s))?;
+
+    if !(0.0..=100.0).contains(&percent) {
+        return Err(format!("percentage `{}` is not between 0 and 100", s));
+    }
+
+    Ok(percent)
+}
+
+/// Parses sizes such as `512M`, `2GiB` or `1024K` into KiB
+fn parse_size(s: &str) -> Result<u64, String> {
+    let digits_end = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(digits_end);
+
+    let value: f64 = value.parse().map_err(|_| format!("invalid size `{}`", s))?;
+
+    let kib_per_unit = match unit.trim() {
+        "K" | "KiB" => 1,
+        "M" | "MiB" => 1024,
+        "G" | "GiB" => 1024 * 1024,
+        "T" | "TiB" => 1024 * 1024 * 1024,
+        _ => return Err(format!("unknown unit in `{}`, expected K, M, G or T", s)),
+    };
+
+    Ok((value * kib_per_unit as f64) as u64)
+}
+
+impl FromStr for Threshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if let Some(args) = s.strip_prefix("min(").and_then(|s| s.strip_suffix(')')) {
+            let (first, second) = args
+                .split_once(',')
+                .ok_or_else(|| "expected `min(<percent>%, <size>)`".to_string())?;
+
+            return match (first.parse()?, second.parse()?) {
+                (Threshold::Percent(percent), Threshold::Size(kib))
+                | (Threshold::Size(kib), Threshold::Percent(percent)) => {
+                    Ok(Threshold::MinOf(percent, kib))
+                }
+                _ => Err("expected `min(<percent>%, <size>)`".into()),
+            };
+        }
+
+        // Plain numbers are percentages, as thresholds used to be
+        if s.ends_with('%') || s.parse::<f64>().is_ok() {
+            return parse_percent(s).map(Threshold::Percent);
+        }
+
+        parse_size(s).map(Threshold::Size)
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Threshold::Percent(percent) => write!(f, "{}%", percent),
+            Threshold::Size(kib) => write!(f, "{}KiB", kib),
+            Threshold::MinOf(percent, kib) => write!(f, "min({}%, {}KiB)", percent, kib),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Threshold;
+
+    #[test]
+    fn parse() {
+        assert_eq!("10%".parse(), Ok(Threshold::Percent(10.0)));
+        assert_eq!("2.5".parse(), Ok(Threshold::Percent(2.5)));
+        assert_eq!("512M".parse(), Ok(Threshold::Size(512 * 1024)));
+        assert_eq!("2GiB".parse(), Ok(Threshold::Size(2 * 1024 * 1024)));
+        assert_eq!(
+            "min(10%, 4G)".parse(),
+            Ok(Threshold::MinOf(10.0, 4 * 1024 * 1024))
+        );
+
+        assert!("110%".parse::<Threshold>().is_err());
+        assert!("12 parsecs".parse::<Threshold>().is_err());
+        assert!("min(10%, 5%)".parse::<Threshold>().is_err());
+    }
+
+    #[test]
+    fn min_of() {
+        // 512 GiB of RAM: 10% would be 51.2 GiB, so the size wins
+        let total_kib = 512 * 1024 * 1024;
+        let threshold = Threshold::MinOf(10.0, 4 * 1024 * 1024);
+        assert_eq!(threshold.kib(total_kib), 4 * 1024 * 1024);
+
+        // 1 GiB of RAM: 10% is ~102 MiB, less than 4 GiB
+        assert_eq!(threshold.kib(1024 * 1024), 104857);
+        assert!(threshold.is_met(100 * 1024, 1024 * 1024));
+    }
+}
@@ -1,103 +1,565 @@
-use std::time::Duration;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
 
-use crate::cli::CommandLineArgs;
-use crate::error::Result;
-use crate::kill;
-use crate::memory;
-use crate::memory::MemoryInfo;
+use crate::capabilities::Capabilities;
+use crate::cgroup::{self, CgroupStats};
+use crate::config::{Combine, Config};
+use crate::error::{Error, Result};
+use crate::kill::{self, KillPlan};
+use crate::linux_version::LinuxVersion;
+use crate::memory::pressure::{PressureSnapshot, PressureTrigger, StallWindow, MEMORY_PRESSURE};
+use crate::memory::{Forecast, MemoryHistory, MemoryInfo, Threshold};
+use crate::policy::{PolicyKind, VictimPolicy};
 use crate::process::Process;
+use crate::warning::Warner;
 
+/// How many times in a row the same transient error may be met before it's taken as fatal
+const MAX_REPEATED_ERRORS: u32 = 10;
+
+/// Where the monitor stands. Memory being low moves it from `Okay` (or `Warning`) to
+/// `Elevated`, and to `Critical` once memory has stayed low for `sustain_samples` readings
+/// or `sustain_ms`. It only goes back to `Okay` once memory isn't low and pressure is
+/// below `clear_psi`
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum MemoryStatus {
-    NearTerminal(f32),
     Okay,
+    /// Memory is getting scarce, but not enough to act on it: only warn the user
+    Warning,
+    /// Memory is low, but hasn't been for long enough to act on it
+    Elevated {
+        since: Instant,
+        samples: u32,
+    },
+    /// Memory has been low for long enough: act whenever it is
+    Critical,
+}
+
+impl MemoryStatus {
+    /// Returns the status following a reading in which memory was low (or at the warning
+    /// level) or not, and in which pressure was (or wasn't) below the clear threshold
+    fn next(
+        self,
+        is_low: bool,
+        is_warning: bool,
+        is_cleared: bool,
+        now: Instant,
+        config: &Config,
+    ) -> Self {
+        let is_sustained = |since: Instant, samples: u32| {
+            let enough_samples = config.sustain_samples.map(|min| samples >= min);
+            let long_enough = config
+                .sustain_ms
+                .map(|ms| now.duration_since(since) >= Duration::from_millis(ms));
+
+            match (enough_samples, long_enough) {
+                (None, None) => true,
+                (enough_samples, long_enough) => {
+                    enough_samples == Some(true) || long_enough == Some(true)
+                }
+            }
+        };
+
+        let elevated = |since, samples| {
+            if is_sustained(since, samples) {
+                MemoryStatus::Critical
+            } else {
+                MemoryStatus::Elevated { since, samples }
+            }
+        };
+
+        match self {
+            MemoryStatus::Critical if is_low || !is_cleared => MemoryStatus::Critical,
+            _ if !is_low && is_warning => MemoryStatus::Warning,
+            _ if !is_low => MemoryStatus::Okay,
+            MemoryStatus::Okay | MemoryStatus::Warning | MemoryStatus::Critical => elevated(now, 1),
+            MemoryStatus::Elevated { since, samples } => elevated(since, samples + 1),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            MemoryStatus::Okay => "okay",
+            MemoryStatus::Warning => "warning",
+            MemoryStatus::Elevated { .. } => "elevated",
+            MemoryStatus::Critical => "critical",
+        }
+    }
+}
+
+/// The outcome of a single check, as done by `bustd --once`
+#[derive(Debug)]
+pub enum CheckOutcome {
+    Okay,
+    /// Memory is running low, but not enough to act on it
+    Warning,
+    /// Memory was low and a victim was killed (or would have been, in a dry run)
+    Acted(KillPlan),
+    /// Memory was low but no victim could be killed
+    FailedToAct(Error),
+}
+
+impl CheckOutcome {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CheckOutcome::Okay => 0,
+            CheckOutcome::Warning => 1,
+            CheckOutcome::Acted(_) => 2,
+            CheckOutcome::FailedToAct(_) => 3,
+        }
+    }
+}
+
+impl fmt::Display for CheckOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckOutcome::Okay => write!(f, "OK"),
+            CheckOutcome::Warning => write!(f, "WARNING: memory is running low"),
+            CheckOutcome::Acted(plan) => write!(f, "CRITICAL: memory is low: {}", plan),
+            CheckOutcome::FailedToAct(err) => {
+                write!(
+                    f,
+                    "CRITICAL: memory is low, could not kill a victim: {}",
+                    err
+                )
+            }
+        }
+    }
 }
 
 pub struct Monitor {
     memory_info: MemoryInfo,
     proc_buf: [u8; 50],
-    buf: [u8; 100],
+    buf: [u8; 512],
     status: MemoryStatus,
-    args: CommandLineArgs,
+    /// Recent readings, from which memory exhaustion is forecast
+    history: MemoryHistory,
+    forecast: Option<Forecast>,
+    /// Only read when available RAM is below `near_terminal`
+    pressure: Option<PressureSnapshot>,
+    /// The last value of the PSI condition, along with when it was read
+    last_psi: Option<(Instant, f32)>,
+    /// How far the PSI value is from the cutoff, and how fast it is rising (per ms)
+    psi_trend: Option<(f64, f64)>,
+    config: Config,
+    /// Built from `victim_policy` once and kept across scans,
+    /// since `fastest_grower` goes by what the previous scan saw
+    policy: Box<dyn VictimPolicy>,
+    /// Only present when a PSI trigger was configured and the kernel accepted it
+    trigger: Option<PressureTrigger>,
+    /// Keeps track of stall totals in order to compute windowed pressure
+    stall_window: StallWindow,
+    /// The memory-limited cgroup `bustd` runs in, when container-aware.
+    /// Memory readings come from it and victims are only searched inside of it
+    scope: Option<String>,
+    /// The pressure file to read, either system-wide or that of `scope`
+    pressure_path: String,
+    /// Whether the kernel supports PSI. Only the RAM and swap thresholds are used otherwise
+    psi_available: bool,
+    capabilities: Capabilities,
+    warner: Warner,
+    /// Watched cgroups a process was killed in, and until when they are left alone
+    cgroup_cooldowns: HashMap<String, Instant>,
+    /// How many transient errors were met in a row, and in total
+    consecutive_errors: u32,
+    total_errors: u64,
+    /// The last transient error, and how many times in a row it was met
+    last_error: Option<(String, u32)>,
 }
 
 impl Monitor {
-    /// Determines how much oomf should sleep
-    /// This function is essentially a copy of how earlyoom calculates its sleep time
+    /// Determines how much oomf should sleep, based on how much RAM and swap are left before
+    /// reaching their terminal thresholds and on how fast they (and pressure) are changing.
+    ///
+    /// Until enough readings were taken to measure the fill rate, the maximum expected fill
+    /// rates are used instead, just like earlyoom does.
     ///
     /// Credits: https://github.com/rfjakob/earlyoom/blob/dea92ae67997fcb1a0664489c13d49d09d472d40/main.c#L365
     /// MIT Licensed
     pub fn sleep_time_ms(&self) -> Duration {
-        // Maximum expected memory fill rate as seen
-        // with `stress -m 4 --vm-bytes 4G`
-        const RAM_FILL_RATE: i64 = 6000;
-        // Maximum expected swap fill rate as seen
-        // with membomb on zRAM
-        const SWAP_FILL_RATE: i64 = 800;
-
-        // Maximum and minimum time to sleep, in ms.
-        const MIN_SLEEP: i64 = 100;
-        const MAX_SLEEP: i64 = 1000;
-
-        // TODO: make these percentages configurable by args./config. file
-        const RAM_TERMINAL_PERCENT: f64 = 10.;
-        const SWAP_TERMINAL_PERCENT: f64 = 10.;
-
-        let ram_headroom_kib = (self.memory_info.available_ram_percent as f64
-            - RAM_TERMINAL_PERCENT)
-            * (self.memory_info.total_ram_mb as f64 * 10.0);
-        let swap_headroom_kib = (self.memory_info.available_swap_percent as f64
-            - SWAP_TERMINAL_PERCENT)
-            * (self.memory_info.total_swap_mb as f64 * 10.0);
-
-        let ram_headroom_kib = i64::max(ram_headroom_kib as i64, 0);
-        let swap_headroom_kib = i64::max(swap_headroom_kib as i64, 0);
-
-        let time_to_sleep = ram_headroom_kib / RAM_FILL_RATE + swap_headroom_kib / SWAP_FILL_RATE;
-        let time_to_sleep = i64::min(time_to_sleep, MAX_SLEEP);
-        let time_to_sleep = i64::max(time_to_sleep, MIN_SLEEP);
-
-        Duration::from_millis(time_to_sleep as u64)
-    }
-
-    pub fn new(proc_buf: [u8; 50], mut buf: [u8; 100], args: CommandLineArgs) -> Result<Self> {
-        let memory_info = MemoryInfo::new()?;
-        let status = if memory_info.available_ram_percent <= 15 {
-            MemoryStatus::NearTerminal(memory::pressure::pressure_some_avg10(&mut buf)?)
+        let Config {
+            ram_fill_rate,
+            swap_fill_rate,
+            min_sleep_ms,
+            max_sleep_ms,
+            ram_terminal,
+            swap_terminal,
+            ..
+        } = self.config;
+        let MemoryInfo {
+            available_ram_kib,
+            total_ram_kib,
+            available_swap_kib,
+            total_swap_kib,
+            ..
+        } = self.memory_info;
+
+        let ram_headroom_kib =
+            available_ram_kib.saturating_sub(ram_terminal.kib(total_ram_kib)) as f64;
+        let swap_headroom_kib =
+            available_swap_kib.saturating_sub(swap_terminal.kib(total_swap_kib)) as f64;
+
+        let memory_ms = match self.forecast {
+            // The forecast's rates are in KiB/s
+            Some(forecast) => time_until(
+                ram_headroom_kib + swap_headroom_kib,
+                (forecast.ram_fill_rate + forecast.swap_fill_rate) / 1000.0,
+            ),
+            None => {
+                time_until(ram_headroom_kib, ram_fill_rate as f64)
+                    + time_until(swap_headroom_kib, swap_fill_rate as f64)
+            }
+        };
+
+        let psi_ms = match self.psi_trend {
+            Some((psi_headroom, psi_rate)) => time_until(psi_headroom, psi_rate),
+            None => f64::INFINITY,
+        };
+
+        adaptive_sleep(memory_ms, psi_ms, min_sleep_ms, max_sleep_ms)
+    }
+
+    pub fn new(
+        proc_buf: [u8; 50],
+        mut buf: [u8; 512],
+        mut config: Config,
+        capabilities: Capabilities,
+    ) -> Result<Self> {
+        // Memory limits can only be read from the unified hierarchy
+        let scope = if config.container_aware && capabilities.cgroup_v2 {
+            cgroup::limited_own_cgroup(&mut buf)?
         } else {
-            MemoryStatus::Okay
+            None
+        };
+
+        let pressure_path = match &scope {
+            Some(scope) => {
+                println!(
+                    "[LOG] Running inside of memory-limited cgroup {}: monitoring it instead of the host.",
+                    scope
+                );
+                format!("{}/memory.pressure", cgroup::dir_of(scope))
+            }
+            None => MEMORY_PRESSURE.to_owned(),
+        };
+
+        let psi_available = Self::check_psi(&pressure_path, &mut buf, &capabilities, &mut config);
+
+        let trigger = match config.psi_trigger {
+            Some(_) if psi_available && !capabilities.psi_trigger => {
+                println!("[LOG] PSI triggers are unsupported. Falling back to adaptive sleep.");
+                None
+            }
+            Some(spec) if psi_available => {
+                match PressureTrigger::new(&pressure_path, spec, &mut buf) {
+                    Ok(trigger) => Some(trigger),
+                    Err(err) => {
+                        eprintln!(
+                        "[LOG] Could not register PSI trigger: {}. Falling back to adaptive sleep.",
+                        err
+                    );
+                        None
+                    }
+                }
+            }
+            _ => None,
         };
 
-        Ok(Self {
-            memory_info,
+        for watch in config.cgroups.iter_mut().filter(|watch| watch.kill_cgroup) {
+            if !capabilities.cgroup_kill {
+                println!(
+                    "[LOG] cgroup.kill is unsupported. Choosing victims inside of {} instead.",
+                    watch.pattern
+                );
+                watch.kill_cgroup = false;
+            }
+        }
+
+        if config.victim_policy.policy().is_expensive() && !capabilities.smaps_rollup {
+            println!(
+                "[LOG] smaps_rollup is unsupported, so the {} victim policy can't be used. Falling back to rss.",
+                config.victim_policy.as_str()
+            );
+            config.victim_policy = PolicyKind::Rss;
+        }
+
+        let stall_window = StallWindow::new(Duration::from_millis(config.psi_window_ms));
+        let policy = config.victim_policy.policy();
+
+        let mut monitor = Self {
+            memory_info: MemoryInfo::default(),
             proc_buf,
             buf,
-            status,
-            args,
-        })
+            status: MemoryStatus::Okay,
+            history: MemoryHistory::new(),
+            forecast: None,
+            pressure: None,
+            last_psi: None,
+            psi_trend: None,
+            config,
+            policy,
+            trigger,
+            stall_window,
+            scope,
+            pressure_path,
+            psi_available,
+            capabilities,
+            warner: Warner::new(),
+            cgroup_cooldowns: HashMap::new(),
+            consecutive_errors: 0,
+            total_errors: 0,
+            last_error: None,
+        };
+        monitor.update_memory_stats()?;
+
+        Ok(monitor)
+    }
+
+    /// Returns whether PSI can be read from `pressure_path`. If it can't, the RAM and swap
+    /// thresholds become the only kill condition, defaulting to 10% if none were configured
+    fn check_psi(
+        pressure_path: &str,
+        buf: &mut [u8],
+        capabilities: &Capabilities,
+        config: &mut Config,
+    ) -> bool {
+        let reason = match &capabilities.linux_version {
+            Some(version) if !version.supports_psi() => {
+                let (major, minor) = LinuxVersion::PSI;
+                format!(
+                    "Linux {} is older than {}.{}, which introduced it",
+                    version, major, minor
+                )
+            }
+            // E.g. the kernel was built without `CONFIG_PSI` or booted with `psi=0`
+            _ if !capabilities.psi => format!("{} could not be read", MEMORY_PRESSURE),
+            _ if pressure_path == MEMORY_PRESSURE => return true,
+            // The host supports PSI, but the cgroup we monitor has its own pressure file
+            _ => match PressureSnapshot::from_file(pressure_path, buf) {
+                Ok(_) => return true,
+                Err(err) => format!("{} could not be read ({})", pressure_path, err),
+            },
+        };
+
+        println!(
+            "[LOG] PSI is unavailable: {}. Only the RAM and swap thresholds will be used.",
+            reason
+        );
+
+        if config.kill_ram.is_none() && config.kill_swap.is_none() {
+            config.kill_ram = Some(Threshold::Percent(10.));
+            config.kill_swap = Some(Threshold::Percent(10.));
+            println!("[LOG] No thresholds were configured: defaulting to 10% of RAM and swap.");
+        }
+
+        false
+    }
+
+    /// Returns whether the RAM and swap thresholds are met, or `None` if none applies
+    fn thresholds_met(&self) -> Option<bool> {
+        thresholds_met(
+            self.config.kill_ram,
+            self.config.kill_swap,
+            &self.memory_info,
+        )
     }
 
     fn memory_is_low(&self) -> bool {
-        let terminal_psi = self.args.cutoff_psi;
-        matches!(self.status, MemoryStatus::NearTerminal(psi) if psi >= terminal_psi)
+        let condition = &self.config.psi_condition;
+        let psi_met = self
+            .pressure
+            .as_ref()
+            .is_some_and(|snapshot| condition.is_met(snapshot));
+
+        let is_low = match (self.config.combine, self.thresholds_met()) {
+            (_, None) => psi_met,
+            // Without PSI, there's nothing to combine the thresholds with
+            (_, Some(thresholds_met)) if !self.psi_available => thresholds_met,
+            (Combine::Any, Some(thresholds_met)) => psi_met || thresholds_met,
+            (Combine::All, Some(thresholds_met)) => psi_met && thresholds_met,
+        };
+
+        is_low || self.exhaustion_forecast()
+    }
+
+    /// Returns true if memory is projected to run out within the forecast horizon
+    fn exhaustion_forecast(&self) -> bool {
+        match (self.config.forecast_horizon_ms, self.forecast) {
+            (Some(horizon_ms), Some(forecast)) => {
+                forecast.exhausts_within(Duration::from_millis(horizon_ms))
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true if memory is at the warning level
+    fn is_warning(&self) -> bool {
+        let ram_met = self.config.warn_ram.is_some_and(|threshold| {
+            threshold.is_met(
+                self.memory_info.available_ram_kib,
+                self.memory_info.total_ram_kib,
+            )
+        });
+        let psi_met = match (&self.config.warn_psi_condition, &self.pressure) {
+            (Some(condition), Some(snapshot)) => condition.is_met(snapshot),
+            _ => false,
+        };
+
+        ram_met || psi_met
+    }
+
+    /// Returns true once pressure has fallen below the clear threshold
+    fn pressure_cleared(&self) -> bool {
+        let condition = &self.config.psi_condition;
+        let clear_psi = self.config.clear_psi.unwrap_or(condition.threshold);
+
+        self.pressure
+            .as_ref()
+            .and_then(|snapshot| condition.value(snapshot))
+            .is_none_or(|value| value < clear_psi)
+    }
+
+    /// Returns true if memory is low and has been for long enough
+    fn should_act(&self) -> bool {
+        self.status == MemoryStatus::Critical && self.memory_is_low()
     }
 
-    fn get_victim(&mut self) -> Result<Process> {
-        kill::choose_victim(&mut self.proc_buf, &mut self.buf, &self.args)
+    /// Returns the watched cgroups which are currently over their limits,
+    /// along with the index of the watch they matched
+    fn exceeded_cgroups(&mut self) -> Result<Vec<(usize, String)>> {
+        let mut exceeded = Vec::new();
+
+        let now = Instant::now();
+        self.cgroup_cooldowns.retain(|_, until| *until > now);
+
+        for (idx, watch) in self.config.cgroups.iter().enumerate() {
+            for cgroup in cgroup::expand(&watch.pattern)? {
+                if self.cgroup_cooldowns.contains_key(&cgroup) {
+                    continue;
+                }
+
+                // The cgroup may have been removed since we've listed it
+                let stats = match CgroupStats::read(&cgroup, &mut self.buf) {
+                    Ok(stats) => stats,
+                    Err(_) => continue,
+                };
+
+                if watch.is_exceeded(&stats) {
+                    exceeded.push((idx, cgroup));
+                }
+            }
+        }
+
+        Ok(exceeded)
     }
 
     fn update_memory_stats(&mut self) -> Result<()> {
-        self.memory_info = memory::MemoryInfo::new()?;
-        self.status = if self.memory_info.available_ram_percent <= 15 {
-            let psi = memory::pressure::pressure_some_avg10(&mut self.buf)?;
-            MemoryStatus::NearTerminal(psi)
+        let memory_info = match &self.scope {
+            Some(scope) => MemoryInfo::from_cgroup(scope, &mut self.buf)?,
+            None => MemoryInfo::new(self.config.memory_source, &mut self.buf)?,
+        };
+        let near_terminal = self
+            .config
+            .near_terminal
+            .is_met(memory_info.available_ram_kib, memory_info.total_ram_kib);
+        let now = Instant::now();
+
+        let pressure = if near_terminal && self.psi_available {
+            let mut snapshot = PressureSnapshot::from_file(&self.pressure_path, &mut self.buf)?;
+            self.stall_window.record(now, &mut snapshot);
+            Some(snapshot)
         } else {
-            MemoryStatus::Okay
+            None
         };
+
+        self.record(now, memory_info, pressure);
+
         Ok(())
     }
 
-    fn free_up_memory(&mut self) -> Result<()> {
-        let victim = self.get_victim()?;
+    /// Takes in readings taken at `now`, updating the forecast, the PSI trend and the status
+    fn record(
+        &mut self,
+        now: Instant,
+        memory_info: MemoryInfo,
+        pressure: Option<PressureSnapshot>,
+    ) {
+        self.memory_info = memory_info;
+        self.history.record(now, &self.memory_info);
+        self.forecast = self.history.forecast();
+        if let (true, Some(forecast)) = (self.config.verbose, self.forecast) {
+            let time_to_exhaustion = match forecast.time_to_exhaustion {
+                Some(time) => format!("{:.1}s", time.as_secs_f64()),
+                None => "never".to_owned(),
+            };
+            eprintln!(
+                "[forecast] RAM: {:.0} KiB/s, swap: {:.0} KiB/s, exhausted in: {}",
+                forecast.ram_fill_rate, forecast.swap_fill_rate, time_to_exhaustion
+            );
+        }
+
+        self.pressure = pressure;
+        self.update_psi_trend(now);
+
+        let is_low = self.memory_is_low();
+        let status = self.status.next(
+            is_low,
+            self.is_warning(),
+            !is_low && self.pressure_cleared(),
+            now,
+            &self.config,
+        );
+        if self.config.verbose && status.as_str() != self.status.as_str() {
+            eprintln!("[status] {} -> {}", self.status.as_str(), status.as_str());
+        }
+        self.status = status;
+    }
+
+    fn update_psi_trend(&mut self, now: Instant) {
+        let condition = &self.config.psi_condition;
+        let psi = self
+            .pressure
+            .as_ref()
+            .and_then(|snapshot| condition.value(snapshot));
+
+        self.psi_trend = match (self.last_psi, psi) {
+            (Some((last_read, last_psi)), Some(psi)) if now > last_read => {
+                let elapsed_ms = now.duration_since(last_read).as_secs_f64() * 1000.0;
+                let psi_headroom = f64::from(condition.threshold - psi);
+                Some((psi_headroom, f64::from(psi - last_psi) / elapsed_ms))
+            }
+            _ => None,
+        };
+        self.last_psi = psi.map(|psi| (now, psi));
+    }
+
+    /// Kills `victim` (or its process group) and returns which signal was sent to what.
+    /// In a dry run, nothing is actually sent
+    fn kill(&self, victim: Process) -> Result<KillPlan> {
+        let plan = KillPlan::new(&victim, self.config.kill_pgroup)?;
+
+        if self.config.dry_run {
+            println!("[LOG] Dry run: would have sent {}.", plan);
+        } else if plan.pgid.is_some() {
+            kill::kill_process_group(&plan)?;
+        } else {
+            kill::kill_and_wait(victim, &plan, &self.capabilities)?;
+        }
+
+        Ok(plan)
+    }
+
+    /// Returns what was done to free up memory, if the situation
+    /// wasn't solved while a victim was being chosen
+    fn free_up_memory(&mut self) -> Result<Option<KillPlan>> {
+        let victim = kill::choose_victim(
+            &mut self.proc_buf,
+            &mut self.buf,
+            &self.config,
+            &*self.policy,
+            &self.capabilities,
+            self.scope.as_deref(),
+        )?;
 
         // TODO: is this necessary?
         //
@@ -105,34 +567,669 @@ impl Monitor {
         // low-memory situation was solved while
         // we were searching for our victim
         self.update_memory_stats()?;
-        if self.memory_is_low() {
-            if self.args.kill_pgroup {
-                kill::kill_process_group(victim)?;
+        if self.should_act() {
+            return self.kill(victim).map(Some);
+        }
+        Ok(None)
+    }
+
+    /// Kills a process inside of `cgroup`, which went over the limits of the watch at `watch_idx`
+    fn free_up_cgroup_memory(&mut self, watch_idx: usize, cgroup: &str) -> Result<()> {
+        println!("[LOG] cgroup {} is over its limits.", cgroup);
+
+        let watch = &self.config.cgroups[watch_idx];
+        let cooldown = Duration::from_millis(watch.cooldown_ms);
+        if watch.kill_cgroup {
+            if self.config.dry_run {
+                println!(
+                    "[LOG] Dry run: would have killed every process in {}.",
+                    cgroup
+                );
             } else {
-                kill::kill_and_wait(victim)?;
+                cgroup::kill(cgroup)?;
+                println!("[LOG] Killed every process in {}.", cgroup);
+            }
+            self.cgroup_cooldowns
+                .insert(cgroup.to_owned(), Instant::now() + cooldown);
+            return Ok(());
+        }
+
+        let victim = kill::choose_victim(
+            &mut self.proc_buf,
+            &mut self.buf,
+            &self.config,
+            &*self.policy,
+            &self.capabilities,
+            Some(cgroup),
+        )?;
+
+        // Just like in `free_up_memory`, check if the situation
+        // was solved while we were searching for our victim
+        let stats = CgroupStats::read(cgroup, &mut self.buf)?;
+        if self.config.cgroups[watch_idx].is_exceeded(&stats) {
+            self.kill(victim)?;
+            self.cgroup_cooldowns
+                .insert(cgroup.to_owned(), Instant::now() + cooldown);
+        }
+        Ok(())
+    }
+
+    /// Waits until memory should be checked again
+    fn wait(&mut self) -> Result<()> {
+        // Calculating the adaptive sleep time
+        let sleep_time = self.sleep_time_ms();
+
+        let trigger = match &self.trigger {
+            Some(trigger) => trigger,
+            None => {
+                if self.config.verbose {
+                    eprintln!("[adaptive-sleep] {}ms", sleep_time.as_millis());
+                }
+                std::thread::sleep(sleep_time);
+                return Ok(());
+            }
+        };
+
+        // While memory is fine, we'll only be woken up by the trigger (or by
+        // `psi_trigger_timeout_ms`, so that memory readings don't get too stale).
+        // Otherwise, keep the adaptive sleep since the trigger only fires once per window.
+        // So do checks which don't depend on PSI, since they can't fire the trigger
+        let timeout = match (&self.pressure, self.status) {
+            (None, MemoryStatus::Okay) if !self.config.has_non_psi_checks() => {
+                Duration::from_millis(self.config.psi_trigger_timeout_ms)
+            }
+            _ => sleep_time,
+        };
+
+        match trigger.wait(timeout) {
+            Ok(fired) => {
+                if self.config.verbose && fired {
+                    eprintln!("[psi-trigger] fired");
+                }
+            }
+            Err(err) => {
+                eprintln!(
+                    "[LOG] PSI trigger failed: {}. Falling back to adaptive sleep.",
+                    err
+                );
+                self.trigger = None;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn memory_info(&self) -> &MemoryInfo {
+        &self.memory_info
+    }
+
+    /// Acts on the reading taken when the monitor was created, if needed, instead of polling
+    pub fn check_once(&mut self) -> CheckOutcome {
+        if self.should_act() {
+            match self.free_up_memory() {
+                Ok(Some(plan)) => return CheckOutcome::Acted(plan),
+                Err(err) => return CheckOutcome::FailedToAct(err),
+                // The situation was solved while a victim was being chosen
+                Ok(None) => {}
             }
         }
+
+        match self.status {
+            MemoryStatus::Okay => CheckOutcome::Okay,
+            _ => CheckOutcome::Warning,
+        }
+    }
+
+    /// Reads memory and acts on it once
+    fn check(&mut self) -> Result<()> {
+        // Update our memory readings
+        self.update_memory_stats()?;
+        if self.should_act() {
+            self.free_up_memory()?;
+        }
+
+        // Memory being low without us acting on it yet is also worth a warning
+        self.warner.reap();
+        if matches!(
+            self.status,
+            MemoryStatus::Warning | MemoryStatus::Elevated { .. }
+        ) {
+            self.warner
+                .warn(Instant::now(), &self.config, &self.memory_info);
+        }
+
+        for (watch_idx, cgroup) in self.exceeded_cgroups()? {
+            self.free_up_cgroup_memory(watch_idx, &cgroup)?;
+        }
+
         Ok(())
     }
 
+    /// Keeps count of a transient error and returns how many times in a row it was met
+    fn count_error(&mut self, err: &Error) -> u32 {
+        self.consecutive_errors += 1;
+        self.total_errors += 1;
+
+        let message = err.to_string();
+        let repeats = match &self.last_error {
+            Some((last, repeats)) if *last == message => repeats + 1,
+            _ => 1,
+        };
+        self.last_error = Some((message, repeats));
+
+        repeats
+    }
+
+    /// Polls memory until a fatal error is met. Transient errors, which are likely
+    /// when the system is under stress, are logged and retried after a backoff, unless
+    /// the same one keeps coming back, in which case it's unlikely to go away after all
     // Use the never type here whenever it reaches stable
     #[allow(unreachable_code)]
     pub fn poll(&mut self) -> Result<()> {
         loop {
-            // Update our memory readings
-            self.update_memory_stats()?;
-            if self.memory_is_low() {
-                self.free_up_memory()?;
-            }
+            match self.check() {
+                Ok(()) => {
+                    self.consecutive_errors = 0;
+                    self.last_error = None;
+                }
+                Err(err) if err.is_transient() => {
+                    let repeats = self.count_error(&err);
+                    if repeats >= MAX_REPEATED_ERRORS {
+                        eprintln!(
+                            "[LOG] Giving up after the same error {} times in a row: {}",
+                            repeats, err
+                        );
+                        return Err(err);
+                    }
 
-            // Calculating the adaptive sleep time
-            let sleep_time = self.sleep_time_ms();
-            if self.args.verbose {
-                eprintln!("[adaptive-sleep] {}ms", sleep_time.as_millis());
+                    let sleep_time = backoff(
+                        self.consecutive_errors,
+                        self.config.min_sleep_ms,
+                        self.config.max_sleep_ms,
+                    );
+                    eprintln!(
+                        "[LOG] Transient error: {} ({} in a row, {} in total). Retrying in {}ms.",
+                        err,
+                        self.consecutive_errors,
+                        self.total_errors,
+                        sleep_time.as_millis()
+                    );
+                    std::thread::sleep(sleep_time);
+                    continue;
+                }
+                Err(err) => return Err(err),
             }
 
-            std::thread::sleep(sleep_time);
+            self.wait()?;
         }
         Ok(())
     }
 }
+
+/// Returns how long it takes, in ms, to go through `headroom` at `rate` (per ms)
+fn time_until(headroom: f64, rate: f64) -> f64 {
+    if rate > 0.0 {
+        f64::max(headroom, 0.0) / rate
+    } else {
+        f64::INFINITY
+    }
+}
+
+/// Returns whether the configured RAM and swap thresholds are all met, or `None` if none
+/// applies. Without swap, there's no swap left to fill up, so a swap threshold is ignored
+/// rather than counted as met, which would make memory look low on every reading
+fn thresholds_met(
+    kill_ram: Option<Threshold>,
+    kill_swap: Option<Threshold>,
+    memory_info: &MemoryInfo,
+) -> Option<bool> {
+    let ram_met = kill_ram.map(|threshold| {
+        threshold.is_met(memory_info.available_ram_kib, memory_info.total_ram_kib)
+    });
+    let swap_met = kill_swap
+        .filter(|_| memory_info.total_swap_kib > 0)
+        .map(|threshold| {
+            threshold.is_met(memory_info.available_swap_kib, memory_info.total_swap_kib)
+        });
+
+    match (ram_met, swap_met) {
+        (Some(ram_met), Some(swap_met)) => Some(ram_met && swap_met),
+        (met, None) | (None, met) => met,
+    }
+}
+
+/// Doubles the time waited after each consecutive error, starting from `min_sleep_ms`
+/// and up to `max_sleep_ms`, so that the host stays protected while errors persist
+fn backoff(consecutive_errors: u32, min_sleep_ms: u64, max_sleep_ms: u64) -> Duration {
+    let factor = 1_u64 << consecutive_errors.saturating_sub(1).min(16);
+    Duration::from_millis(min_sleep_ms.saturating_mul(factor).min(max_sleep_ms))
+}
+
+/// Sleeps until right before memory or pressure are expected to become terminal,
+/// given in how many ms they would, but within `min_sleep_ms` and `max_sleep_ms`
+fn adaptive_sleep(memory_ms: f64, psi_ms: f64, min_sleep_ms: u64, max_sleep_ms: u64) -> Duration {
+    let time_to_sleep = f64::min(memory_ms, psi_ms);
+    let time_to_sleep = f64::min(time_to_sleep, max_sleep_ms as f64) as u64;
+    let time_to_sleep = u64::max(time_to_sleep, min_sleep_ms);
+
+    Duration::from_millis(time_to_sleep)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use std::process::Command;
+
+    use libc::SIGTERM;
+
+    use super::{backoff, thresholds_met, CheckOutcome, MemoryStatus, Monitor};
+    use crate::capabilities::Capabilities;
+    use crate::config::Config;
+    use crate::error::Error;
+    use crate::memory::pressure::PressureSnapshot;
+    use crate::memory::{MemoryHistory, MemoryInfo, Threshold};
+    use crate::process::Process;
+    use crate::tier::Tiers;
+    use crate::utils;
+
+    fn dry_run_monitor(config: Config) -> Monitor {
+        let config = Config {
+            dry_run: true,
+            ..config
+        };
+        let mut buf = [0_u8; 512];
+        let capabilities = Capabilities::probe(None, &mut buf);
+
+        Monitor::new([0_u8; 50], buf, config, capabilities).unwrap()
+    }
+
+    #[test]
+    fn hysteresis() {
+        let config = Config {
+            sustain_samples: Some(3),
+            ..Config::default()
+        };
+        let now = Instant::now();
+
+        // Memory must stay low for three readings in a row
+        let status = MemoryStatus::Okay.next(true, false, false, now, &config);
+        let status = status.next(true, false, false, now, &config);
+        assert_eq!(
+            status.next(false, false, false, now, &config),
+            MemoryStatus::Okay
+        );
+        let status = status.next(true, false, false, now, &config);
+        assert_eq!(status, MemoryStatus::Critical);
+
+        // Pressure between the clear threshold and the cutoff keeps it critical
+        let status = status.next(false, false, false, now, &config);
+        assert_eq!(status, MemoryStatus::Critical);
+        assert_eq!(
+            status.next(false, false, true, now, &config),
+            MemoryStatus::Okay
+        );
+
+        // Or for long enough
+        let config = Config {
+            sustain_ms: Some(500),
+            ..Config::default()
+        };
+        let status = MemoryStatus::Okay.next(true, false, false, now, &config);
+        assert_eq!(
+            status.next(
+                true,
+                false,
+                false,
+                now + Duration::from_millis(100),
+                &config
+            ),
+            MemoryStatus::Elevated {
+                since: now,
+                samples: 2
+            }
+        );
+        assert_eq!(
+            status.next(
+                true,
+                false,
+                false,
+                now + Duration::from_millis(500),
+                &config
+            ),
+            MemoryStatus::Critical
+        );
+
+        // Warnings don't count towards being sustained
+        let status = MemoryStatus::Okay.next(false, true, false, now, &config);
+        assert_eq!(status, MemoryStatus::Warning);
+        assert_eq!(
+            status.next(true, true, false, now, &config),
+            MemoryStatus::Elevated {
+                since: now,
+                samples: 1
+            }
+        );
+
+        // Without any requirement, act right away
+        let status = MemoryStatus::Okay.next(true, false, false, now, &Config::default());
+        assert_eq!(status, MemoryStatus::Critical);
+    }
+
+    #[test]
+    fn sleep_shrinks_as_pressure_builds() {
+        const GIB: u64 = 1024 * 1024;
+
+        let config = Config {
+            min_sleep_ms: 100,
+            max_sleep_ms: 10_000,
+            ..Config::default()
+        };
+        let reading = |available_ram_kib| MemoryInfo {
+            total_ram_kib: 8 * GIB,
+            available_ram_kib,
+            ..MemoryInfo::default()
+        };
+        let pressure = |avg10| {
+            let mut snapshot = PressureSnapshot::default();
+            snapshot.some.avg10 = avg10;
+            snapshot
+        };
+        // Without the readings taken of this host when starting up
+        let fresh_monitor = || {
+            let mut monitor = dry_run_monitor(config.clone());
+            monitor.history = MemoryHistory::new();
+            monitor.last_psi = None;
+            monitor
+        };
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // Idle: once there are enough readings to go by, nothing is filling up,
+        // so poll as rarely as allowed
+        let mut monitor = fresh_monitor();
+        for ms in 0..8 {
+            monitor.record(at(ms * 1000), reading(4 * GIB), None);
+        }
+        assert_eq!(monitor.sleep_time_ms(), Duration::from_millis(10_000));
+
+        // Memory filling up ever faster, as seen by a growing history of readings
+        let mut monitor = fresh_monitor();
+        let mut sleeps = Vec::new();
+        for step in 0..10 {
+            let used = step * step * GIB / 20;
+            monitor.record(at(step * 100), reading(6 * GIB - used), None);
+            sleeps.push(monitor.sleep_time_ms());
+        }
+        // Until there are enough readings, the worst-case fill rates are assumed
+        assert!(sleeps[..3]
+            .iter()
+            .all(|&sleep| sleep < Duration::from_secs(1)));
+        assert!(sleeps[3..].windows(2).all(|pair| pair[1] < pair[0]));
+
+        // Rising pressure alone is enough to poll more often, but never below the minimum
+        let mut monitor = fresh_monitor();
+        let mut sleeps = Vec::new();
+        for (step, avg10) in [0.0, 4.0, 8.0, 12.0, 16.0, 20.0, 24.0].iter().enumerate() {
+            let at = at(step as u64 * 100);
+            monitor.record(at, reading(4 * GIB), Some(pressure(*avg10)));
+            sleeps.push(monitor.sleep_time_ms());
+        }
+        assert!(sleeps[1..6].windows(2).all(|pair| pair[1] < pair[0]));
+        assert_eq!(sleeps[6], Duration::from_millis(100));
+    }
+
+    #[test]
+    fn warning_transitions() {
+        let config = Config::default();
+        let now = Instant::now();
+        let next = |status: MemoryStatus, is_low, is_warning, is_cleared| {
+            status.next(is_low, is_warning, is_cleared, now, &config)
+        };
+
+        assert_eq!(
+            next(MemoryStatus::Okay, false, true, true),
+            MemoryStatus::Warning
+        );
+        assert_eq!(
+            next(MemoryStatus::Warning, false, true, true),
+            MemoryStatus::Warning
+        );
+        assert_eq!(
+            next(MemoryStatus::Warning, false, false, true),
+            MemoryStatus::Okay
+        );
+        // Without a sustain requirement, a warning escalates right away
+        assert_eq!(
+            next(MemoryStatus::Warning, true, true, false),
+            MemoryStatus::Critical
+        );
+        // Once acting, only a cleared reading steps down to a warning
+        assert_eq!(
+            next(MemoryStatus::Critical, false, true, false),
+            MemoryStatus::Critical
+        );
+        assert_eq!(
+            next(MemoryStatus::Critical, false, true, true),
+            MemoryStatus::Warning
+        );
+        let elevated = MemoryStatus::Elevated {
+            since: now,
+            samples: 1,
+        };
+        assert_eq!(next(elevated, false, true, true), MemoryStatus::Warning);
+    }
+
+    #[test]
+    fn thresholds() {
+        let ten_percent = Some(Threshold::Percent(10.0));
+        let memory_info = |available_ram_kib, available_swap_kib, total_swap_kib| MemoryInfo {
+            total_ram_kib: 1000,
+            available_ram_kib,
+            total_swap_kib,
+            available_swap_kib,
+            ..MemoryInfo::default()
+        };
+
+        // Nothing configured
+        assert_eq!(thresholds_met(None, None, &memory_info(50, 50, 1000)), None);
+
+        // No swap: a swap threshold alone never applies
+        let no_swap = memory_info(580, 0, 0);
+        assert_eq!(thresholds_met(None, ten_percent, &no_swap), None);
+        assert_eq!(
+            thresholds_met(ten_percent, ten_percent, &no_swap),
+            Some(false)
+        );
+        assert_eq!(
+            thresholds_met(ten_percent, ten_percent, &memory_info(50, 0, 0)),
+            Some(true)
+        );
+
+        // RAM only
+        assert_eq!(
+            thresholds_met(ten_percent, None, &memory_info(50, 1000, 1000)),
+            Some(true)
+        );
+        assert_eq!(
+            thresholds_met(ten_percent, None, &memory_info(580, 0, 1000)),
+            Some(false)
+        );
+
+        // Swap only
+        assert_eq!(
+            thresholds_met(None, ten_percent, &memory_info(580, 50, 1000)),
+            Some(true)
+        );
+        assert_eq!(
+            thresholds_met(None, ten_percent, &memory_info(50, 580, 1000)),
+            Some(false)
+        );
+
+        // Both must be met
+        assert_eq!(
+            thresholds_met(ten_percent, ten_percent, &memory_info(50, 50, 1000)),
+            Some(true)
+        );
+        assert_eq!(
+            thresholds_met(ten_percent, ten_percent, &memory_info(50, 580, 1000)),
+            Some(false)
+        );
+        assert_eq!(
+            thresholds_met(ten_percent, ten_percent, &memory_info(580, 50, 1000)),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_max() {
+        let backoff_ms = |errors| backoff(errors, 100, 1000).as_millis();
+
+        assert_eq!(backoff_ms(1), 100);
+        assert_eq!(backoff_ms(2), 200);
+        assert_eq!(backoff_ms(4), 800);
+        assert_eq!(backoff_ms(5), 1000);
+        assert_eq!(backoff_ms(u32::MAX), 1000);
+    }
+
+    #[test]
+    fn repeated_errors_are_counted() {
+        let mut monitor = dry_run_monitor(Config::default());
+
+        assert_eq!(monitor.count_error(&Error::ProcessNotFound("kill")), 1);
+        assert_eq!(monitor.count_error(&Error::ProcessNotFound("kill")), 2);
+        // Only the very same error counts
+        assert_eq!(monitor.count_error(&Error::ProcessNotFound("getpgid")), 1);
+        assert_eq!(monitor.count_error(&Error::ProcessNotFound("getpgid")), 2);
+        assert_eq!(monitor.consecutive_errors, 4);
+    }
+
+    #[test]
+    fn dry_run_never_signals() {
+        // Our child shares our process group
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        let victim = Process::from_pid(child.id(), &mut [0_u8; 50]).unwrap();
+
+        let monitor = dry_run_monitor(Config {
+            kill_pgroup: true,
+            ..Config::default()
+        });
+        let plan = monitor.kill(victim).unwrap();
+
+        let own_pgid = utils::get_process_group(std::process::id() as i32).unwrap();
+        assert_eq!(plan.pid, child.id());
+        assert_eq!(plan.pgid, Some(own_pgid));
+        assert_eq!(plan.signal, SIGTERM);
+        assert!(child.try_wait().unwrap().is_none());
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn dry_run_goes_through_the_pipeline() {
+        // Always considered low on memory
+        let mut monitor = dry_run_monitor(Config {
+            kill_ram: Some(Threshold::Percent(100.)),
+            ..Config::default()
+        });
+        assert!(monitor.should_act());
+
+        let plan = monitor.free_up_memory().unwrap().unwrap();
+        assert_eq!(plan.pgid, None);
+        assert!(plan.escalate);
+        assert!(Process::is_alive_from_pid(plan.pid));
+    }
+
+    #[test]
+    fn once_reports_acting() {
+        let mut monitor = dry_run_monitor(Config {
+            kill_ram: Some(Threshold::Percent(100.)),
+            ..Config::default()
+        });
+
+        let outcome = monitor.check_once();
+        assert!(matches!(outcome, CheckOutcome::Acted(_)));
+        assert_eq!(outcome.exit_code(), 2);
+    }
+
+    #[test]
+    fn once_exit_codes() {
+        // Pressure can't get this high, so only the thresholds below matter
+        let config = Config {
+            psi_condition: "full avg10 > 100".parse().unwrap(),
+            ..Config::default()
+        };
+
+        let outcome = dry_run_monitor(config.clone()).check_once();
+        assert!(matches!(outcome, CheckOutcome::Okay));
+        assert_eq!(outcome.exit_code(), 0);
+
+        let outcome = dry_run_monitor(Config {
+            warn_ram: Some(Threshold::Percent(100.)),
+            ..config.clone()
+        })
+        .check_once();
+        assert!(matches!(outcome, CheckOutcome::Warning));
+        assert_eq!(outcome.exit_code(), 1);
+
+        // Every process is protected, so there's nothing to kill
+        let outcome = dry_run_monitor(Config {
+            kill_ram: Some(Threshold::Percent(100.)),
+            tiers: Tiers {
+                protect: vec!["*".parse().unwrap()],
+                ..Tiers::default()
+            },
+            ..config
+        })
+        .check_once();
+        assert!(matches!(outcome, CheckOutcome::FailedToAct(_)));
+        assert_eq!(outcome.exit_code(), 3);
+    }
+
+    #[test]
+    fn once_reports_refused_kills() {
+        // Signals can only be refused to root by dropping its privileges
+        // Safety: geteuid is safe
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+
+        let mut child = Command::new("sleep").arg("30.19").spawn().unwrap();
+
+        let outcome = std::thread::spawn(|| {
+            // Unlike libc's setresuid, the raw syscall only changes the credentials of this
+            // thread, which loses CAP_KILL along with root while other tests keep running as root
+            // Safety: setresuid is memory safe
+            let res = unsafe { libc::syscall(libc::SYS_setresuid, 65534, 65534, 0) };
+            assert_eq!(res, 0);
+
+            let config = Config {
+                kill_ram: Some(Threshold::Percent(100.)),
+                tiers: Tiers {
+                    prefer: vec!["cmdline:sleep 30.19".parse().unwrap()],
+                    ..Tiers::default()
+                },
+                ..Config::default()
+            };
+            let mut buf = [0_u8; 512];
+            let capabilities = Capabilities::probe(None, &mut buf);
+
+            Monitor::new([0_u8; 50], buf, config, capabilities)
+                .unwrap()
+                .check_once()
+        })
+        .join()
+        .unwrap();
+
+        assert!(matches!(
+            outcome,
+            CheckOutcome::FailedToAct(Error::NoPermission)
+        ));
+        assert_eq!(outcome.exit_code(), 3);
+        assert!(child.try_wait().unwrap().is_none());
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
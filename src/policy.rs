@@ -0,0 +1,320 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::error::Result;
+use crate::kill::Candidate;
+use crate::process::Process;
+use crate::utils;
+
+/// Decides which process gets killed: the candidate with the highest score is chosen,
+/// with ties broken by RSS
+pub trait VictimPolicy {
+    fn score(&self, candidate: &Candidate, buf: &mut [u8]) -> Result<i64>;
+
+    /// Returns the score of `process` if it can be known without assessing it first,
+    /// which lets processes that can't beat the current victim be skipped early
+    fn score_upfront(&self, _process: &Process) -> Option<i64> {
+        None
+    }
+
+    /// Whether scoring is slow enough that only the `smaps_candidates`
+    /// candidates with the largest RSS should be scored
+    fn is_expensive(&self) -> bool {
+        false
+    }
+}
+
+/// The kernel's own heuristic, which also accounts for `oom_score_adj`
+pub struct OomScore;
+
+impl VictimPolicy for OomScore {
+    fn score(&self, candidate: &Candidate, _buf: &mut [u8]) -> Result<i64> {
+        Ok(candidate.process.oom_score.into())
+    }
+
+    fn score_upfront(&self, process: &Process) -> Option<i64> {
+        Some(process.oom_score.into())
+    }
+}
+
+/// The process with the largest resident set
+pub struct Rss;
+
+impl VictimPolicy for Rss {
+    fn score(&self, candidate: &Candidate, _buf: &mut [u8]) -> Result<i64> {
+        Ok(candidate.vm_rss_kib)
+    }
+}
+
+/// The process with the most anonymous memory, in RAM or swapped out. Unlike its RSS,
+/// this leaves out file-backed and shared pages, which killing it wouldn't free up
+pub struct AnonSwap;
+
+impl VictimPolicy for AnonSwap {
+    fn score(&self, candidate: &Candidate, buf: &mut [u8]) -> Result<i64> {
+        candidate.process.anon_swap_kib(buf)
+    }
+}
+
+/// The process with the largest proportional set size, which counts pages shared by
+/// several processes (e.g. forked workers) only once between all of them, unlike RSS
+pub struct Pss;
+
+impl VictimPolicy for Pss {
+    fn score(&self, candidate: &Candidate, buf: &mut [u8]) -> Result<i64> {
+        Ok(candidate.process.smaps_rollup(buf)?.pss_kib)
+    }
+
+    fn is_expensive(&self) -> bool {
+        true
+    }
+}
+
+/// The process with the largest unique set size, i.e. that would free up the most memory
+pub struct Uss;
+
+impl VictimPolicy for Uss {
+    fn score(&self, candidate: &Candidate, buf: &mut [u8]) -> Result<i64> {
+        Ok(candidate.process.smaps_rollup(buf)?.uss_kib)
+    }
+
+    fn is_expensive(&self) -> bool {
+        true
+    }
+}
+
+/// The process that started last, which is likely what caused memory to run out
+pub struct Newest;
+
+impl VictimPolicy for Newest {
+    fn score(&self, candidate: &Candidate, buf: &mut [u8]) -> Result<i64> {
+        candidate.process.start_time_ticks(buf)
+    }
+}
+
+/// The RSS of a process as seen by an earlier scan
+#[derive(Debug, Clone, Copy)]
+struct RssSample {
+    /// Tells the process apart from a later one reusing its PID
+    start_time_ticks: i64,
+    rss_kib: i64,
+    at: Instant,
+}
+
+/// Samples older than this are too stale to tell how fast a process is growing right now
+const MAX_SAMPLE_AGE: Duration = Duration::from_secs(300);
+
+/// The process whose RSS grew the fastest since the previous scan, in KiB per second.
+/// Processes the previous scan didn't see are scored by how fast they grew, on average,
+/// since they started, which is exact for those that started in the meantime
+#[derive(Default)]
+pub struct FastestGrower {
+    /// What earlier scans of this instance saw, by PID
+    samples: RefCell<HashMap<u32, RssSample>>,
+}
+
+impl FastestGrower {
+    fn growth(
+        previous: Option<RssSample>,
+        current: RssSample,
+        age_ticks: i64,
+        clock_ticks: i64,
+    ) -> i64 {
+        match previous {
+            Some(previous) if previous.start_time_ticks == current.start_time_ticks => {
+                // Scans at least a second apart, so that a short blip doesn't look like a spike
+                let elapsed_ms = (current.at - previous.at).max(Duration::from_secs(1));
+                (current.rss_kib - previous.rss_kib) * 1000 / elapsed_ms.as_millis() as i64
+            }
+            // Processes count as at least a second old,
+            // so that those which were just spawned don't look like they're spiking
+            _ => current.rss_kib * clock_ticks / age_ticks.max(clock_ticks),
+        }
+    }
+}
+
+impl VictimPolicy for FastestGrower {
+    fn score(&self, candidate: &Candidate, buf: &mut [u8]) -> Result<i64> {
+        let clock_ticks = utils::clock_ticks()?;
+        let start_time_ticks = candidate.process.start_time_ticks(buf)?;
+        let age_ticks = utils::uptime_ticks()? - start_time_ticks;
+        let current = RssSample {
+            start_time_ticks,
+            rss_kib: candidate.vm_rss_kib,
+            at: Instant::now(),
+        };
+
+        let mut samples = self.samples.borrow_mut();
+        let previous = samples
+            .get(&candidate.process.pid)
+            .copied()
+            .filter(|previous| current.at - previous.at <= MAX_SAMPLE_AGE);
+
+        // Keep measuring against the older sample when scans follow each other closely
+        if previous.is_none_or(|previous| current.at - previous.at >= Duration::from_secs(1)) {
+            if samples.len() >= 4096 {
+                samples.retain(|_, sample| current.at - sample.at <= MAX_SAMPLE_AGE);
+            }
+            samples.insert(candidate.process.pid, current);
+        }
+
+        Ok(Self::growth(previous, current, age_ticks, clock_ticks))
+    }
+}
+
+/// The built-in victim policies, as chosen through `victim_policy`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolicyKind {
+    OomScore,
+    Rss,
+    AnonSwap,
+    Pss,
+    Uss,
+    Newest,
+    FastestGrower,
+}
+
+impl PolicyKind {
+    /// Builds a new instance of this policy. Policies may remember what they saw in earlier
+    /// scans, so the same instance should be kept around for as long as victims are chosen
+    pub fn policy(self) -> Box<dyn VictimPolicy> {
+        match self {
+            PolicyKind::OomScore => Box::new(OomScore),
+            PolicyKind::Rss => Box::new(Rss),
+            PolicyKind::AnonSwap => Box::new(AnonSwap),
+            PolicyKind::Pss => Box::new(Pss),
+            PolicyKind::Uss => Box::new(Uss),
+            PolicyKind::Newest => Box::new(Newest),
+            PolicyKind::FastestGrower => Box::new(FastestGrower::default()),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PolicyKind::OomScore => "oom_score",
+            PolicyKind::Rss => "rss",
+            PolicyKind::AnonSwap => "anon_swap",
+            PolicyKind::Pss => "pss",
+            PolicyKind::Uss => "uss",
+            PolicyKind::Newest => "newest",
+            PolicyKind::FastestGrower => "fastest_grower",
+        }
+    }
+}
+
+impl FromStr for PolicyKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "oom_score" => Ok(Self::OomScore),
+            "rss" => Ok(Self::Rss),
+            "anon_swap" => Ok(Self::AnonSwap),
+            "pss" => Ok(Self::Pss),
+            "uss" => Ok(Self::Uss),
+            "newest" => Ok(Self::Newest),
+            "fastest_grower" => Ok(Self::FastestGrower),
+            _ => Err(format!(
+                "unknown victim policy `{}`, expected `oom_score`, `rss`, `anon_swap`, `pss`, `uss`, `newest` or `fastest_grower`",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use std::time::{Duration, Instant};
+
+    use super::{FastestGrower, PolicyKind, RssSample, VictimPolicy};
+    use crate::kill::Candidate;
+    use crate::process::Process;
+    use crate::tier::Tier;
+
+    fn candidate(pid: u32, buf: &mut [u8]) -> Candidate {
+        let process = Process::from_pid(pid, buf).unwrap();
+
+        Candidate {
+            vm_rss_kib: process.vm_rss_kib(buf).unwrap(),
+            oom_score_adj: process.oom_score_adj(buf).unwrap(),
+            tier: Tier::Normal,
+            process,
+        }
+    }
+
+    #[test]
+    fn newest_prefers_children() {
+        let mut buf = [0_u8; 512];
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+
+        let policy = PolicyKind::Newest.policy();
+        let ours = policy
+            .score(&candidate(std::process::id(), &mut buf), &mut buf)
+            .unwrap();
+        let childs = policy
+            .score(&candidate(child.id(), &mut buf), &mut buf)
+            .unwrap();
+        assert!(childs >= ours);
+
+        let anon_swap = PolicyKind::AnonSwap
+            .policy()
+            .score(&candidate(std::process::id(), &mut buf), &mut buf)
+            .unwrap();
+        assert!(anon_swap > 0);
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn fastest_grower_keeps_its_own_samples() {
+        let mut buf = [0_u8; 512];
+        let ours = candidate(std::process::id(), &mut buf);
+
+        let first = FastestGrower::default();
+        let second = FastestGrower::default();
+        first.score(&ours, &mut buf).unwrap();
+
+        assert!(first.samples.borrow().contains_key(&ours.process.pid));
+        assert!(second.samples.borrow().is_empty());
+    }
+
+    #[test]
+    fn fastest_grower_measures_recent_growth() {
+        let now = Instant::now();
+        let sample = |rss_kib, secs_ago| RssSample {
+            start_time_ticks: 100,
+            rss_kib,
+            at: now - Duration::from_secs(secs_ago),
+        };
+        let week_ticks = 7 * 24 * 3600 * 100;
+
+        // A week-old daemon which just grew by 10 GiB in 10 seconds
+        let grown = FastestGrower::growth(
+            Some(sample(1024, 10)),
+            sample(10 * 1024 * 1024 + 1024, 0),
+            week_ticks,
+            100,
+        );
+        assert_eq!(grown, 1024 * 1024);
+
+        // A 2 second old process with 50 MiB, which the previous scan didn't see
+        let spawned = FastestGrower::growth(None, sample(50 * 1024, 0), 200, 100);
+        assert_eq!(spawned, 25 * 1024);
+        assert!(grown > spawned);
+
+        // Another process reusing the PID since
+        let reused = RssSample {
+            start_time_ticks: 200,
+            ..sample(1024, 0)
+        };
+        assert_eq!(
+            FastestGrower::growth(Some(sample(0, 10)), reused, 100, 100),
+            1024
+        );
+    }
+}
@@ -1,24 +1,42 @@
 use std::io::Read;
 use std::io::Write;
+use std::os::unix::fs::MetadataExt;
 
 use libc::getpgid;
 
 use crate::{
+    cgroup,
     error::{Error, Result},
+    kill::PidFd,
     utils::{self, str_from_u8},
 };
 
+/// Memory usage which, unlike RSS, accounts for pages shared with other processes
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SmapsRollup {
+    /// Proportional set size: private pages, plus shared pages divided by how many processes share them
+    pub pss_kib: i64,
+    /// Unique set size: private pages only, i.e. what killing the process would free up
+    pub uss_kib: i64,
+}
+
 #[derive(Debug, Default)]
 pub struct Process {
     pub pid: u32,
     pub oom_score: i16,
+    /// Only opened once the process is chosen as a victim, when supported
+    pub pidfd: Option<PidFd>,
 }
 
 impl Process {
     pub fn from_pid(pid: u32, buf: &mut [u8]) -> Result<Self> {
         let oom_score =
             Self::oom_score_from_pid(pid, buf).or(Err(Error::ProcessNotFound("from_pid")))?;
-        Ok(Self { pid, oom_score })
+        Ok(Self {
+            pid,
+            oom_score,
+            pidfd: None,
+        })
     }
 
     #[allow(dead_code)]
@@ -93,24 +111,147 @@ impl Process {
         Ok(vm_rss_kib)
     }
 
-    #[cfg(feature = "glob-ignore")]
-    /// Checks if the process' name matches any of the given glob patterns
-    pub fn is_unkillable(&self, mut buf: &mut [u8], patterns: &[String]) -> Result<bool> {
-        use glob::Pattern;
-
-        let comm = self.comm(buf)?;
-        for pattern in patterns {
-            let pattern = Pattern::new(pattern)?;
-            if pattern.matches(comm) {
-                println!(
-                    "Skipping \"{}\" since it matches an unkillable pattern",
-                    comm
-                );
-                return Ok(true);
+    /// Reads RssAnon and VmSwap from /proc/<PID>/status and returns their sum, i.e. how much
+    /// memory would actually be freed by killing the process, shared memory aside
+    pub fn anon_swap_kib(&self, buf: &mut [u8]) -> Result<i64> {
+        write!(&mut *buf, "/proc/{}/status\0", self.pid)?;
+        let file = utils::file_from_buffer(buf)?;
+
+        let mut anon_swap_kib = 0;
+        let mut parse_error = None;
+        utils::for_each_line(file, buf, |line| {
+            let value = match line.split_once(':') {
+                Some(("RssAnon", value)) | Some(("VmSwap", value)) => value,
+                _ => return,
+            };
+            match utils::parse_kib(value) {
+                Ok(kib) => anon_swap_kib += kib,
+                Err(err) => parse_error = Some(err),
+            }
+        })?;
+
+        match parse_error {
+            Some(err) => Err(err.into()),
+            None => Ok(anon_swap_kib),
+        }
+    }
+
+    /// Reads PSS and USS from /proc/<PID>/smaps_rollup, which is considerably slower than
+    /// reading `statm` since the kernel walks through every mapping of the process
+    pub fn smaps_rollup(&self, buf: &mut [u8]) -> Result<SmapsRollup> {
+        write!(&mut *buf, "/proc/{}/smaps_rollup\0", self.pid)?;
+        let file = utils::file_from_buffer(buf)?;
+
+        let mut smaps_rollup = SmapsRollup::default();
+        let mut parse_error = None;
+        utils::for_each_line(file, buf, |line| {
+            let (key, value) = match line.split_once(':') {
+                Some((key @ ("Pss" | "Private_Clean" | "Private_Dirty"), value)) => (key, value),
+                _ => return,
+            };
+            let kib = match utils::parse_kib(value) {
+                Ok(kib) => kib,
+                Err(err) => return parse_error = Some(err),
+            };
+            match key {
+                "Pss" => smaps_rollup.pss_kib = kib,
+                _ => smaps_rollup.uss_kib += kib,
+            }
+        })?;
+
+        match parse_error {
+            Some(err) => Err(err.into()),
+            None => Ok(smaps_rollup),
+        }
+    }
+
+    /// Reads, from /proc/<PID>/stat, when the process started, in clock ticks since boot
+    pub fn start_time_ticks(&self, buf: &mut [u8]) -> Result<i64> {
+        write!(&mut *buf, "/proc/{}/stat\0", self.pid)?;
+        let contents = {
+            let mut file = utils::file_from_buffer(buf)?;
+            buf.fill(0);
+            let _ = file.read(buf)?;
+
+            str_from_u8(buf)?
+        };
+
+        // The name of the process may contain spaces and parentheses, so only look at what
+        // follows its closing parenthesis. The start time is the 22nd field, the state the 3rd
+        let (_, fields) = contents.rsplit_once(')').ok_or(Error::MalformedStat)?;
+        let start_time = fields
+            .split_ascii_whitespace()
+            .nth(22 - 3)
+            .ok_or(Error::MalformedStat)?
+            .parse::<i64>()?;
+
+        Ok(start_time)
+    }
+
+    /// Returns true if the process belongs to the given v2 cgroup (relative to
+    /// the root of the hierarchy) or to one of its descendants
+    pub fn is_in_cgroup(&self, buf: &mut [u8], cgroup: &str) -> Result<bool> {
+        write!(&mut *buf, "/proc/{}/cgroup\0", self.pid)?;
+        let file = utils::file_from_buffer(buf)?;
+
+        let mut is_in = false;
+        utils::for_each_line(file, buf, |line| {
+            // The line of the unified hierarchy looks like `0::/user.slice/user-1000.slice`
+            if let Some(path) = line.strip_prefix("0::") {
+                is_in = cgroup::is_descendant(path, cgroup);
+            }
+        })?;
+
+        Ok(is_in)
+    }
+
+    /// Returns the v2 cgroup of the process, relative to the root of the hierarchy,
+    /// or None if the unified hierarchy isn't mounted
+    pub fn cgroup(&self, buf: &mut [u8]) -> Result<Option<String>> {
+        write!(&mut *buf, "/proc/{}/cgroup\0", self.pid)?;
+        let file = utils::file_from_buffer(buf)?;
+
+        let mut cgroup = None;
+        utils::for_each_line(file, buf, |line| {
+            if let Some(path) = line.strip_prefix("0::") {
+                cgroup = Some(path.to_owned());
+            }
+        })?;
+
+        Ok(cgroup)
+    }
+
+    /// Returns the path of the executable of the process. Unlike its name, the process
+    /// can't change it. None for kernel threads, and for processes of other users if not root
+    pub fn exe(&self) -> Result<Option<String>> {
+        match std::fs::read_link(format!("/proc/{}/exe", self.pid)) {
+            Ok(exe) => Ok(Some(exe.to_string_lossy().into_owned())),
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied
+                ) =>
+            {
+                Ok(None)
             }
+            Err(err) => Err(err.into()),
         }
+    }
+
+    /// Returns the ID of the user the process runs as (its effective user ID)
+    pub fn uid(&self) -> Result<u32> {
+        let metadata = std::fs::metadata(format!("/proc/{}", self.pid))?;
+
+        Ok(metadata.uid())
+    }
+
+    /// Returns the full command line of the process, with its arguments separated by spaces.
+    /// Empty for kernel threads
+    pub fn cmdline(&self) -> Result<String> {
+        let cmdline = std::fs::read(format!("/proc/{}/cmdline", self.pid))?;
+        let cmdline = String::from_utf8_lossy(&cmdline);
 
-        Ok(false)
+        Ok(cmdline.trim_end_matches('\0').replace('\0', " "))
     }
 
     pub fn oom_score_adj(&self, buf: &mut [u8]) -> Result<i16> {
@@ -134,7 +275,6 @@ mod tests {
     //
     // The reason we don't use `procfs` directly is
     // because our implementation is considerably leaner.
-    use procfs;
 
     // Returns the Process representing the
     // process of the caller test
@@ -165,6 +305,34 @@ mod tests {
         assert_eq!(this.oom_score, _this.oom_score().unwrap() as i16);
     }
 
+    #[test]
+    fn start_time_ticks() {
+        let (_, this) = this();
+        // The start time is too far into the file for a smaller buffer
+        let mut buf = [0_u8; 512];
+
+        let _this = procfs::process::Process::myself().unwrap();
+
+        assert_eq!(
+            this.start_time_ticks(&mut buf).unwrap(),
+            _this.stat.starttime as i64
+        );
+    }
+
+    #[test]
+    fn smaps_rollup() {
+        let (_, this) = this();
+        let mut buf = [0_u8; 512];
+
+        let smaps_rollup = this.smaps_rollup(&mut buf).unwrap();
+        let vm_rss_kib = this.vm_rss_kib(&mut buf).unwrap();
+
+        // Shared pages only partly count towards PSS, and not at all towards USS
+        assert!(smaps_rollup.uss_kib > 0);
+        assert!(smaps_rollup.uss_kib <= smaps_rollup.pss_kib);
+        assert!(smaps_rollup.pss_kib <= vm_rss_kib);
+    }
+
     #[test]
     fn pid() {
         let (_, this) = this();
@@ -0,0 +1,240 @@
+use std::{fmt, str::FromStr};
+
+use crate::error::Result;
+use crate::process::Process;
+use crate::utils;
+
+/// What a rule matches against
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    /// The name of the process, which the kernel truncates
+    /// to 15 characters and which the process can change
+    Comm,
+    /// The full command line
+    Cmdline,
+    /// The resolved path of the executable
+    Exe,
+    /// The ID of the user the process runs as
+    Uid,
+    /// The name of the user the process runs as
+    User,
+    /// The v2 cgroup of the process, e.g. `/system.slice/sshd.service`
+    Cgroup,
+}
+
+impl Field {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Field::Comm => "comm",
+            Field::Cmdline => "cmdline",
+            Field::Exe => "exe",
+            Field::Uid => "uid",
+            Field::User => "user",
+            Field::Cgroup => "cgroup",
+        }
+    }
+
+    fn from_prefix(prefix: &str) -> Option<Self> {
+        match prefix {
+            "comm" => Some(Field::Comm),
+            "cmdline" => Some(Field::Cmdline),
+            "exe" => Some(Field::Exe),
+            "uid" => Some(Field::Uid),
+            "user" => Some(Field::User),
+            "cgroup" => Some(Field::Cgroup),
+            _ => None,
+        }
+    }
+
+    /// Reads this field of `process`. None if it doesn't have one, e.g. when the
+    /// unified cgroup hierarchy isn't mounted or the executable can't be resolved
+    fn read(&self, process: &Process, buf: &mut [u8]) -> Result<Option<String>> {
+        Ok(match self {
+            Field::Comm => Some(process.comm(buf)?.trim().to_owned()),
+            Field::Cmdline => Some(process.cmdline()?),
+            Field::Exe => process.exe()?,
+            Field::Uid => Some(process.uid()?.to_string()),
+            Field::User => utils::username_of(process.uid()?),
+            Field::Cgroup => process.cgroup(buf)?,
+        })
+    }
+}
+
+/// The fields of a process, each read the first time a rule needs it, so
+/// that matching it against several rules doesn't read the same file twice
+pub struct Fields<'a> {
+    process: &'a Process,
+    values: [Option<Option<String>>; 6],
+}
+
+impl<'a> Fields<'a> {
+    pub fn new(process: &'a Process) -> Self {
+        Self {
+            process,
+            values: Default::default(),
+        }
+    }
+
+    pub fn get(&mut self, field: Field, buf: &mut [u8]) -> Result<Option<&str>> {
+        let value = &mut self.values[field as usize];
+        if value.is_none() {
+            *value = Some(field.read(self.process, buf)?);
+        }
+
+        Ok(value.as_ref().and_then(|value| value.as_deref()))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    /// `*` matches any sequence of characters, `?` any single character and `[...]` any of
+    /// the characters in brackets, just like in unkillable patterns
+    Glob(glob::Pattern),
+    /// A user name without wildcards, resolved to its ID (as matched against `Field::Uid`)
+    /// when the rule is parsed, so that users don't have to be looked up on every scan
+    User { name: String, uid: String },
+    /// Matches anywhere in the field unless anchored
+    #[cfg(feature = "regex-rules")]
+    Regex(regex::Regex),
+}
+
+/// Matches processes by one of their fields, written as `[field:][re:]pattern`, e.g.
+/// `sshd`, `cmdline:*idea*`, `user:postgres` or `exe:re:^/usr/lib/jvm/`. Patterns are
+/// globs unless prefixed with `re:`, and match against `comm` if no field is given
+#[derive(Debug, Clone)]
+pub struct Rule {
+    field: Field,
+    matcher: Matcher,
+}
+
+impl Rule {
+    /// Returns true if `value`, a field of a process, matches this rule
+    pub fn matches_value(&self, value: &str) -> bool {
+        match &self.matcher {
+            Matcher::Glob(pattern) => pattern.matches(value),
+            Matcher::User { uid, .. } => value == uid,
+            #[cfg(feature = "regex-rules")]
+            Matcher::Regex(regex) => regex.is_match(value),
+        }
+    }
+
+    pub fn matches(&self, fields: &mut Fields, buf: &mut [u8]) -> Result<bool> {
+        let field = match self.matcher {
+            Matcher::User { .. } => Field::Uid,
+            _ => self.field,
+        };
+        let value = fields.get(field, buf)?;
+
+        Ok(value.is_some_and(|value| self.matches_value(value)))
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (field, pattern) = match s.split_once(':') {
+            Some((prefix, pattern)) => match Field::from_prefix(prefix) {
+                Some(field) => (field, pattern),
+                // A colon that's part of the pattern
+                None => (Field::Comm, s),
+            },
+            None => (Field::Comm, s),
+        };
+
+        let matcher = match pattern.strip_prefix("re:") {
+            #[cfg(feature = "regex-rules")]
+            Some(regex) => Matcher::Regex(
+                regex::Regex::new(regex)
+                    .map_err(|err| format!("invalid regex `{}`: {}", regex, err))?,
+            ),
+            #[cfg(not(feature = "regex-rules"))]
+            Some(_) => {
+                return Err(format!(
+                    "`{}` is a regex, but bustd was built without the `regex-rules` feature",
+                    s
+                ))
+            }
+            None => match (field, utils::uid_of(pattern)) {
+                (Field::User, Some(uid)) if !pattern.contains(['*', '?', '[']) => Matcher::User {
+                    name: pattern.to_owned(),
+                    uid: uid.to_string(),
+                },
+                _ => Matcher::Glob(
+                    glob::Pattern::new(pattern)
+                        .map_err(|err| format!("invalid glob `{}`: {}", pattern, err))?,
+                ),
+            },
+        };
+
+        Ok(Self { field, matcher })
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.field != Field::Comm {
+            write!(f, "{}:", self.field.as_str())?;
+        }
+        match &self.matcher {
+            Matcher::Glob(pattern) => write!(f, "{}", pattern.as_str()),
+            Matcher::User { name, .. } => write!(f, "{}", name),
+            #[cfg(feature = "regex-rules")]
+            Matcher::Regex(regex) => write!(f, "re:{}", regex),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Field, Fields, Rule};
+    use crate::process::Process;
+    use crate::utils;
+
+    #[test]
+    fn parse_and_match() {
+        let rule: Rule = "cmdline:java *-jar*idea*".parse().unwrap();
+        assert_eq!(rule.field, Field::Cmdline);
+        assert!(rule.matches_value("java -Xmx2g -jar /opt/idea/lib/idea.jar"));
+        assert!(!rule.matches_value("java -jar /srv/service.jar"));
+        assert_eq!(rule.to_string(), "cmdline:java *-jar*idea*");
+
+        // Not a field, so part of the pattern
+        let rule: Rule = "weird:name".parse().unwrap();
+        assert_eq!(rule.field, Field::Comm);
+        assert!(rule.matches_value("weird:name"));
+
+        // Same syntax as unkillable patterns
+        let rule: Rule = "python[23]".parse().unwrap();
+        assert!(rule.matches_value("python3"));
+        assert!(!rule.matches_value("python"));
+        assert!("chrom[e".parse::<Rule>().is_err());
+
+        // Matches ourselves
+        let mut buf = [0_u8; 512];
+        let this = Process::this(&mut buf).unwrap();
+        let user = utils::get_username().unwrap();
+        let mut fields = Fields::new(&this);
+        let rule: Rule = format!("user:{}", user).parse().unwrap();
+        assert!(rule.matches(&mut fields, &mut buf).unwrap());
+        assert_eq!(rule.to_string(), format!("user:{}", user));
+        let rule: Rule = "exe:/*".parse().unwrap();
+        assert!(rule.matches(&mut fields, &mut buf).unwrap());
+
+        // Kernel threads have no executable, which doesn't make them unreadable
+        if let Ok(kthreadd) = Process::from_pid(2, &mut buf) {
+            let mut fields = Fields::new(&kthreadd);
+            assert!(!rule.matches(&mut fields, &mut buf).unwrap());
+        }
+
+        #[cfg(feature = "regex-rules")]
+        {
+            let rule: Rule = "exe:re:^/usr/(local/)?bin/".parse().unwrap();
+            assert!(rule.matches_value("/usr/local/bin/node"));
+            assert!(!rule.matches_value("/opt/usr/bin/node"));
+            assert!("re:(".parse::<Rule>().is_err());
+        }
+        #[cfg(not(feature = "regex-rules"))]
+        assert!("re:.*".parse::<Rule>().is_err());
+    }
+}
@@ -0,0 +1,102 @@
+use crate::error::Result;
+use crate::rule::{Fields, Rule};
+
+/// Which processes are killed first: victims are only chosen from a tier once there's
+/// none left in the tiers before it. Ordered from the last tier to the first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Tier {
+    /// Never killed
+    Protected,
+    /// Only killed once there's nothing else left
+    Avoided,
+    Normal,
+    /// Killed before anything else
+    Expendable,
+}
+
+impl Tier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Tier::Protected => "protected",
+            Tier::Avoided => "avoided",
+            Tier::Normal => "normal",
+            Tier::Expendable => "expendable",
+        }
+    }
+}
+
+/// Rules sorting processes into tiers. Processes matching none are `Normal`
+#[derive(Debug, Clone, Default)]
+pub struct Tiers {
+    pub prefer: Vec<Rule>,
+    pub avoid: Vec<Rule>,
+    pub protect: Vec<Rule>,
+}
+
+impl Tiers {
+    pub fn is_empty(&self) -> bool {
+        self.prefer.is_empty() && self.avoid.is_empty() && self.protect.is_empty()
+    }
+
+    /// Returns the tier of the process whose `fields` are given. Being protected
+    /// wins over being avoided, which wins over being preferred
+    pub fn tier_of(&self, fields: &mut Fields, buf: &mut [u8]) -> Result<Tier> {
+        self.tier_by(|rule| rule.matches(fields, buf))
+    }
+
+    fn tier_by(&self, mut matches: impl FnMut(&Rule) -> Result<bool>) -> Result<Tier> {
+        let mut any_matches = |rules: &[Rule]| -> Result<bool> {
+            for rule in rules {
+                if matches(rule)? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        };
+
+        Ok(if any_matches(&self.protect)? {
+            Tier::Protected
+        } else if any_matches(&self.avoid)? {
+            Tier::Avoided
+        } else if any_matches(&self.prefer)? {
+            Tier::Expendable
+        } else {
+            Tier::Normal
+        })
+    }
+
+    /// The tier victims are looked for in first
+    pub fn first(&self) -> Tier {
+        if self.prefer.is_empty() {
+            Tier::Normal
+        } else {
+            Tier::Expendable
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Tier, Tiers};
+
+    #[test]
+    fn tier_of() {
+        let rules = |rules: &[&str]| rules.iter().map(|rule| rule.parse().unwrap()).collect();
+        let tiers = Tiers {
+            prefer: rules(&["chrom*", "*-language-server"]),
+            avoid: rules(&["Xorg", "chromium"]),
+            protect: rules(&["sshd"]),
+        };
+        let tier_of = |comm| tiers.tier_by(|rule| Ok(rule.matches_value(comm))).unwrap();
+
+        assert_eq!(tier_of("chrome"), Tier::Expendable);
+        assert_eq!(tier_of("rust-language-server"), Tier::Expendable);
+        assert_eq!(tier_of("chromium"), Tier::Avoided);
+        assert_eq!(tier_of("sshd"), Tier::Protected);
+        assert_eq!(tier_of("bash"), Tier::Normal);
+        assert_eq!(tiers.first(), Tier::Expendable);
+
+        assert!(Tier::Expendable > Tier::Normal && Tier::Avoided > Tier::Protected);
+        assert_eq!(Tiers::default().first(), Tier::Normal);
+    }
+}
@@ -1,9 +1,15 @@
 use std::fs::File;
-use std::{ffi::CStr, mem, ptr, str};
+use std::io::Read;
+use std::{
+    ffi::{CStr, CString},
+    mem,
+    num::ParseIntError,
+    ptr, str,
+};
 
-use libc::_SC_PAGESIZE;
 use libc::{getpgid, sysconf, EINVAL, EPERM, ESRCH};
-use libc::{getpwuid_r, passwd};
+use libc::{getpwnam_r, getpwuid_r, passwd};
+use libc::{_SC_CLK_TCK, _SC_PAGESIZE};
 
 use crate::errno::errno;
 use crate::error::{Error, Result};
@@ -47,12 +53,68 @@ pub fn page_size() -> Result<i64> {
     Ok(page_size.into())
 }
 
+/// How many clock ticks there are in a second, the unit of times in `/proc/<PID>/stat`
+pub fn clock_ticks() -> Result<i64> {
+    let clock_ticks = unsafe { sysconf(_SC_CLK_TCK) };
+    if clock_ticks == -1 {
+        return Err(Error::SysConfFailed);
+    }
+
+    #[allow(clippy::useless_conversion)]
+    Ok(clock_ticks.into())
+}
+
+/// Time since boot, suspended time included, in clock ticks
+pub fn uptime_ticks() -> Result<i64> {
+    // Safety: the all-zero byte pattern is a valid timespec
+    let mut time: libc::timespec = unsafe { mem::zeroed() };
+
+    // Safety: `time` is a valid reference
+    if unsafe { libc::clock_gettime(libc::CLOCK_BOOTTIME, &mut time) } == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    let clock_ticks = clock_ticks()?;
+    #[allow(clippy::useless_conversion)]
+    Ok(
+        i64::from(time.tv_sec) * clock_ticks
+            + i64::from(time.tv_nsec) * clock_ticks / 1_000_000_000,
+    )
+}
+
 pub fn get_username() -> Option<String> {
+    username_of(effective_user_id())
+}
+
+/// Looks up the ID of the user with the given name
+pub fn uid_of(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
     let mut buf = [0; 2048];
     let mut result = ptr::null_mut();
     let mut passwd: passwd = unsafe { mem::zeroed() };
 
-    let uid = effective_user_id();
+    let getpwnam_r_code = unsafe {
+        getpwnam_r(
+            name.as_ptr(),
+            &mut passwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+
+    if getpwnam_r_code == 0 && !result.is_null() {
+        return Some(passwd.pw_uid);
+    }
+
+    None
+}
+
+/// Looks up the name of the user with the given ID
+pub fn username_of(uid: u32) -> Option<String> {
+    let mut buf = [0; 2048];
+    let mut result = ptr::null_mut();
+    let mut passwd: passwd = unsafe { mem::zeroed() };
 
     let getpwuid_r_code =
         unsafe { getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result) };
@@ -78,11 +140,140 @@ pub fn str_from_u8(buf: &[u8]) -> Result<&str> {
 
 pub fn file_from_buffer(buf: &[u8]) -> Result<File> {
     let path = str_from_u8(buf)?;
-    let file = File::open(&path)?;
+    let file = File::open(path)?;
     Ok(file)
 }
 
-pub fn bytes_to_megabytes(bytes: impl Into<u64>, mem_unit: impl Into<u64>) -> u64 {
-    const B_TO_MB: u64 = 1000 * 1000;
-    bytes.into() / B_TO_MB * mem_unit.into()
+pub fn bytes_to_kib(bytes: impl Into<u64>, mem_unit: impl Into<u64>) -> u64 {
+    bytes.into() * mem_unit.into() / 1024
+}
+
+/// Parses a value of a `/proc` file given in KiB, which look like `    1234 kB`
+pub fn parse_kib(value: &str) -> std::result::Result<i64, ParseIntError> {
+    value.trim().trim_end_matches("kB").trim_end().parse()
+}
+
+/// Reads `file` through `buf`, calling `f` on every line it contains.
+///
+/// `buf` does not have to be large enough to hold the whole file, only its
+/// longest line, which lets us parse files such as `/proc/meminfo` without
+/// allocating. Returns `Error::LineTooLong` otherwise.
+pub fn for_each_line(mut file: File, buf: &mut [u8], mut f: impl FnMut(&str)) -> Result<()> {
+    // How many bytes of `buf` are currently filled
+    let mut filled = 0;
+
+    loop {
+        let read = file.read(&mut buf[filled..])?;
+        filled += read;
+
+        let mut line_start = 0;
+        while let Some(len) = buf[line_start..filled].iter().position(|&c| c == b'\n') {
+            f(str::from_utf8(&buf[line_start..line_start + len])?);
+            line_start += len + 1;
+        }
+
+        if read == 0 {
+            // We've reached EOF, so whatever is left is the last line
+            if line_start < filled {
+                f(str::from_utf8(&buf[line_start..filled])?);
+            }
+            return Ok(());
+        }
+
+        if line_start == 0 && filled == buf.len() {
+            return Err(Error::LineTooLong);
+        }
+
+        // Move the incomplete line to the start of the buffer
+        buf.copy_within(line_start..filled, 0);
+        filled -= line_start;
+    }
+}
+
+/// Matches `text` against a pattern in which `*` matches any sequence of
+/// characters and `?` matches any single character
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let text = text.as_bytes();
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` seen and of the text when we saw it
+    let mut backtrack = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` eat one more character
+                Some((star_p, star_t)) => {
+                    backtrack = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Quotes `text` as a JSON string
+pub fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{json_string, parse_kib, wildcard_match};
+
+    #[test]
+    fn kib_values() {
+        assert_eq!(parse_kib("    1234 kB"), Ok(1234));
+        assert_eq!(parse_kib("\t0 kB"), Ok(0));
+        assert!(parse_kib(" kB").is_err());
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(wildcard_match("docker-*.scope", "docker-1a2b.scope"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("user-100?.slice", "user-1000.slice"));
+        assert!(wildcard_match("*a*b", "xxaxxab"));
+        assert!(!wildcard_match("docker-*.scope", "docker-1a2b.service"));
+        assert!(!wildcard_match("user.slice", "user.slices"));
+    }
+
+    #[test]
+    fn json_strings() {
+        assert_eq!(json_string("firefox"), r#""firefox""#);
+        assert_eq!(
+            json_string("sh -c \"echo \\\"hi\\\"\"\n"),
+            r#""sh -c \"echo \\\"hi\\\"\"\n""#
+        );
+        assert_eq!(json_string("\u{1b}[0m"), r#""\u001b[0m""#);
+    }
 }
@@ -0,0 +1,110 @@
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::memory::MemoryInfo;
+
+/// Lets the user know that memory is running low, without killing anything,
+/// so that they get a chance to close things themselves
+pub struct Warner {
+    /// When the last warning was given
+    last_warning: Option<Instant>,
+    /// Notifications and hooks which haven't exited yet
+    children: Vec<Child>,
+}
+
+impl Warner {
+    pub fn new() -> Self {
+        Self {
+            last_warning: None,
+            children: Vec::new(),
+        }
+    }
+
+    /// Logs a warning and runs the configured notification and hook, unless a warning
+    /// was already given within `warn_interval_ms`. Returns true if a warning was given
+    pub fn warn(&mut self, now: Instant, config: &Config, memory_info: &MemoryInfo) -> bool {
+        let interval = Duration::from_millis(config.warn_interval_ms);
+        if self
+            .last_warning
+            .is_some_and(|last| now.duration_since(last) < interval)
+        {
+            return false;
+        }
+        self.last_warning = Some(now);
+
+        let message = format!(
+            "Memory is running low: {:.1}% of RAM and {:.1}% of swap available",
+            memory_info.available_ram_percent, memory_info.available_swap_percent
+        );
+        println!("[WARN] {}", message);
+
+        if config.warn_notify {
+            let mut command = Command::new("notify-send");
+            command.args(["--urgency=critical", "bustd", &message]);
+            self.spawn(command);
+        }
+
+        if let Some(hook) = &config.warn_hook {
+            let mut command = Command::new("/bin/sh");
+            command
+                .args(["-c", hook])
+                .env("BUSTD_MESSAGE", &message)
+                .env(
+                    "BUSTD_AVAILABLE_RAM_PERCENT",
+                    format!("{:.1}", memory_info.available_ram_percent),
+                )
+                .env(
+                    "BUSTD_AVAILABLE_SWAP_PERCENT",
+                    format!("{:.1}", memory_info.available_swap_percent),
+                );
+            self.spawn(command);
+        }
+
+        true
+    }
+
+    fn spawn(&mut self, mut command: Command) {
+        match command.spawn() {
+            Ok(child) => self.children.push(child),
+            Err(err) => eprintln!("[LOG] Could not run {:?}: {}", command, err),
+        }
+    }
+
+    /// Waits on the notifications and hooks which have exited in the meantime
+    pub fn reap(&mut self) {
+        self.children
+            .retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Warner;
+    use crate::config::Config;
+    use crate::memory::MemoryInfo;
+
+    #[test]
+    fn rate_limited() {
+        // No notification nor hook gets run
+        let config = Config {
+            warn_interval_ms: 60_000,
+            ..Config::default()
+        };
+        let memory_info = MemoryInfo::default();
+        let now = Instant::now();
+        let after = |ms| now + Duration::from_millis(ms);
+
+        let mut warner = Warner::new();
+        assert!(warner.warn(now, &config, &memory_info));
+        assert!(!warner.warn(now, &config, &memory_info));
+        assert!(!warner.warn(after(59_999), &config, &memory_info));
+        assert!(warner.warn(after(60_000), &config, &memory_info));
+        // The interval starts over from the last warning given
+        assert!(!warner.warn(after(90_000), &config, &memory_info));
+        assert!(warner.warn(after(120_000), &config, &memory_info));
+        assert!(warner.children.is_empty());
+    }
+}